@@ -0,0 +1,147 @@
+//! Security report over the entries of a vault. It's computed from the decrypted entries on
+//! every request and never stored.
+
+use std::{cmp::Reverse, collections::HashMap};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::entry::{EntryData, VaultEntry};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// What the audit counts as weak or old.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct AuditOptions {
+    /// Passwords with a strength score below this are weak.
+    pub min_score: u8,
+    /// Passwords that haven't changed for this many days are old.
+    pub max_password_age_days: u64,
+}
+
+impl Default for AuditOptions {
+    fn default() -> Self {
+        Self {
+            min_score: 3,
+            max_password_age_days: 365,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct WeakPassword {
+    pub id: Uuid,
+    pub score: u8,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct OldPassword {
+    pub id: Uuid,
+    /// Days since the password was last changed.
+    pub age_days: u64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct AuditReport {
+    /// Groups of two or more logins sharing a password, largest group first.
+    pub reused_passwords: Vec<Vec<Uuid>>,
+    /// Weakest first.
+    pub weak_passwords: Vec<WeakPassword>,
+    /// Oldest first.
+    pub old_passwords: Vec<OldPassword>,
+    /// Logins whose url is plain `http://`.
+    pub insecure_urls: Vec<Uuid>,
+}
+
+/// Audit the logins in `entries` as of `now`, see [crate::entry::unix_timestamp].
+pub fn audit(entries: &HashMap<Uuid, VaultEntry>, options: &AuditOptions, now: u64) -> AuditReport {
+    // Sorted by title so the report doesn't change order between calls.
+    let mut logins: Vec<&VaultEntry> = entries
+        .values()
+        .filter(|entry| matches!(entry.data, EntryData::Login { .. }))
+        .collect();
+    logins.sort_by(|a, b| (&a.title, a.id).cmp(&(&b.title, b.id)));
+
+    let mut report = AuditReport::default();
+    let mut by_password: HashMap<&str, Vec<Uuid>> = HashMap::new();
+    for entry in logins {
+        let EntryData::Login { password, url, .. } = &entry.data else {
+            continue;
+        };
+
+        if !password.unsecure().is_empty() {
+            by_password
+                .entry(password.unsecure())
+                .or_default()
+                .push(entry.id);
+        }
+        if let Some(strength) = entry.password_strength() {
+            if strength.score < options.min_score {
+                report.weak_passwords.push(WeakPassword {
+                    id: entry.id,
+                    score: strength.score,
+                });
+            }
+        }
+        let age_days = now.saturating_sub(entry.password_changed()) / SECONDS_PER_DAY;
+        if age_days >= options.max_password_age_days {
+            report.old_passwords.push(OldPassword {
+                id: entry.id,
+                age_days,
+            });
+        }
+        if url.trim().to_ascii_lowercase().starts_with("http://") {
+            report.insecure_urls.push(entry.id);
+        }
+    }
+
+    report.reused_passwords = by_password
+        .into_values()
+        .filter(|ids| ids.len() > 1)
+        .collect();
+    report
+        .reused_passwords
+        .sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    report.weak_passwords.sort_by_key(|weak| weak.score);
+    report
+        .old_passwords
+        .sort_by_key(|old| Reverse(old.age_days));
+    report
+}
+
+#[test]
+fn test_audit() {
+    let now = 1_700_000_000;
+    let login = |title: &str, password: &str, url: &str, changed_days_ago: u64| {
+        let mut entry = VaultEntry::new("user".into(), password.into(), url.into());
+        entry.title = title.into();
+        entry.created = now - changed_days_ago * SECONDS_PER_DAY;
+        entry
+    };
+    let shared = "correct-horse-battery-staple-7";
+    let entries: HashMap<Uuid, VaultEntry> = [
+        login("a", shared, "https://a.example", 10),
+        login("b", shared, "HTTP://b.example", 400),
+        login("c", "password", "https://c.example", 10),
+        login("d", "x7#Qm!2vLp9@wZ4k", "http://d.example", 800),
+    ]
+    .into_iter()
+    .map(|entry| (entry.id, entry))
+    .collect();
+    let id = |title: &str| entries.values().find(|e| e.title == title).unwrap().id;
+
+    let report = audit(&entries, &AuditOptions::default(), now);
+    assert_eq!(report.reused_passwords, vec![vec![id("a"), id("b")]]);
+    assert_eq!(
+        report.weak_passwords,
+        vec![WeakPassword {
+            id: id("c"),
+            score: 0
+        }]
+    );
+    let old: Vec<_> = report.old_passwords.iter().map(|old| old.id).collect();
+    assert_eq!(old, vec![id("d"), id("b")]);
+    assert_eq!(report.old_passwords[1].age_days, 400);
+    assert_eq!(report.insecure_urls, vec![id("b"), id("d")]);
+}
@@ -0,0 +1,161 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+use serde::Serialize;
+
+/// A previous version of a vault kept next to it on disk. Backups are encrypted exactly like
+/// the vault itself.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    /// `1` is the most recent backup.
+    pub index: usize,
+    pub path: PathBuf,
+    /// Seconds since the unix epoch.
+    pub modified: u64,
+    pub size: u64,
+}
+
+/// Path of the `index`th backup of the vault at `path`, e.g. `vault.bak.1`.
+pub fn backup_path(path: &Path, index: usize) -> PathBuf {
+    with_suffix(path, &format!(".bak.{}", index))
+}
+
+/// Replace the file at `path` with `bytes` without ever leaving a partially written file behind.
+///
+/// The bytes are written to a temporary file next to `path` and flushed to disk first. Then the
+/// existing backups are rotated, the current file becomes backup `1` (only `backup_count`
+/// backups are kept), and finally the temporary file is renamed over `path`.
+pub fn write_with_backups(path: &Path, bytes: &[u8], backup_count: usize) -> io::Result<()> {
+    let temp_path = write_temp(path, bytes)?;
+    if path.exists() {
+        rotate_backups(path, backup_count)?;
+    }
+    fs::rename(&temp_path, path)?;
+    sync_parent_dir(path)
+}
+
+/// Replace the file at `path` with `bytes` like [write_with_backups], but leave the backups
+/// alone. For changes that aren't worth a backup of their own.
+pub fn write_without_backup(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let temp_path = write_temp(path, bytes)?;
+    fs::rename(&temp_path, path)?;
+    sync_parent_dir(path)
+}
+
+/// Returns the backups of the vault at `path` that exist on disk, most recent first.
+pub fn list_backups(path: &Path, backup_count: usize) -> io::Result<Vec<BackupInfo>> {
+    let mut backups = Vec::new();
+    for index in 1..=backup_count {
+        let backup_path = backup_path(path, index);
+        let metadata = match fs::metadata(&backup_path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        backups.push(BackupInfo {
+            index,
+            path: backup_path,
+            modified,
+            size: metadata.len(),
+        });
+    }
+    Ok(backups)
+}
+
+/// Shift every backup up by one, dropping the ones past `backup_count`, and copy the current
+/// file at `path` to backup `1`.
+fn rotate_backups(path: &Path, backup_count: usize) -> io::Result<()> {
+    // Remove the oldest backup, and any left over from when more backups were kept.
+    let mut index = backup_count.max(1);
+    while backup_path(path, index).exists() {
+        fs::remove_file(backup_path(path, index))?;
+        index += 1;
+    }
+    if backup_count == 0 {
+        return Ok(());
+    }
+
+    for index in (1..backup_count).rev() {
+        let from = backup_path(path, index);
+        if from.exists() {
+            fs::rename(from, backup_path(path, index + 1))?;
+        }
+    }
+
+    let newest = backup_path(path, 1);
+    fs::copy(path, &newest)?;
+    File::open(newest)?.sync_all()
+}
+
+/// Write `bytes` to a temporary file next to `path`, flush it to disk and return its path. Only
+/// the user can read the file.
+fn write_temp(path: &Path, bytes: &[u8]) -> io::Result<PathBuf> {
+    let temp_path = with_suffix(path, ".tmp");
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&temp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    Ok(temp_path)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name: OsString = path.file_name().unwrap_or_default().into();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Make sure the rename of a file in the directory is persisted.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[test]
+fn test_rotate_backups() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vault");
+
+    for version in 1..=4u8 {
+        write_with_backups(&path, &[version], 2).unwrap();
+    }
+    assert_eq!(fs::read(&path).unwrap(), [4]);
+    assert_eq!(fs::read(backup_path(&path, 1)).unwrap(), [3]);
+    assert_eq!(fs::read(backup_path(&path, 2)).unwrap(), [2]);
+    assert!(!backup_path(&path, 3).exists());
+    assert!(!with_suffix(&path, ".tmp").exists());
+
+    let backups = list_backups(&path, 2).unwrap();
+    assert_eq!(
+        backups.iter().map(|b| b.index).collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+
+    // Lowering the backup count cleans up the backups that are no longer kept.
+    write_with_backups(&path, &[5], 1).unwrap();
+    assert_eq!(fs::read(backup_path(&path, 1)).unwrap(), [4]);
+    assert!(!backup_path(&path, 2).exists());
+}
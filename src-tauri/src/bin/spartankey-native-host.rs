@@ -0,0 +1,197 @@
+//! Native messaging host for the Spartan Key browser extension.
+//!
+//! Chrome starts this binary when the extension connects and talks to it over stdin and stdout.
+//! Every message from the extension is passed on to the running app over its local socket and
+//! the app's answer is passed back, see `protocol.rs` for the framing both sides use. The host
+//! says hello to the app with the token from `protocol::token_path` when it connects, the
+//! extension only ever sends the requests that follow. The extension pairs with the app and
+//! encrypts its vault requests itself, so the host only ever sees ciphertext.
+//!
+//! `spartankey-native-host --install <extension id>...` registers the host with Chrome and
+//! Chromium for the current user, `--uninstall` removes it again.
+
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+#[path = "../protocol.rs"]
+mod protocol;
+
+use protocol::{read_message, write_message};
+
+/// Name the extension connects to, it has to match the `name` in the manifest.
+const HOST_NAME: &str = "com.spartankey.native_host";
+
+/// Config directories of the browsers the manifest is installed for, relative to
+/// `$XDG_CONFIG_HOME`.
+const BROWSER_DIRS: [&str; 4] = [
+    "google-chrome",
+    "google-chrome-beta",
+    "google-chrome-unstable",
+    "chromium",
+];
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("--install") => install(&args[1..]),
+        Some("--uninstall") => uninstall(),
+        // Started by the browser, which passes the extension's origin.
+        _ => relay(),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Pass messages from the extension to the app and its answers back until the extension
+/// disconnects.
+fn relay() -> io::Result<()> {
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    let mut app = None;
+    while let Some(request) = read_message(&mut stdin)? {
+        let response = forward(&mut app, &request).unwrap_or_else(|e| {
+            // Reconnect on the next message, the app may have been restarted.
+            app = None;
+            error_message(&e)
+        });
+        write_message(&mut stdout, &response)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn forward(
+    app: &mut Option<std::os::unix::net::UnixStream>,
+    request: &[u8],
+) -> io::Result<Vec<u8>> {
+    let stream = match app {
+        Some(stream) => stream,
+        None => {
+            let mut stream = std::os::unix::net::UnixStream::connect(protocol::socket_path())?;
+            let hello = serde_json::json!({
+                "type": "hello",
+                "version": protocol::PROTOCOL_VERSION,
+                "token": fs::read_to_string(protocol::token_path())?.trim(),
+            });
+            let response = exchange(&mut stream, &serde_json::to_vec(&hello)?)?;
+            let accepted = serde_json::from_slice::<serde_json::Value>(&response)
+                .is_ok_and(|response| response.get("hello").is_some());
+            if !accepted {
+                // The app's error, e.g. an unsupported version, goes straight to the extension.
+                return Ok(response);
+            }
+            app.insert(stream)
+        }
+    };
+    exchange(stream, request)
+}
+
+/// Send one request to the app and wait for its response.
+#[cfg(unix)]
+fn exchange(stream: &mut std::os::unix::net::UnixStream, request: &[u8]) -> io::Result<Vec<u8>> {
+    write_message(stream, request)?;
+    read_message(stream)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::ConnectionReset,
+            "Spartan Key closed the connection",
+        )
+    })
+}
+
+#[cfg(not(unix))]
+fn forward(_app: &mut Option<()>, _request: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "The browser integration isn't supported on this platform yet",
+    ))
+}
+
+/// An error in the same shape as the app's errors, so the extension handles both the same way.
+fn error_message(error: &io::Error) -> Vec<u8> {
+    let (kind, message) = match error.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => {
+            ("NotRunning", "Spartan Key isn't running".to_string())
+        }
+        _ => ("Io", format!("IoError: {}", error)),
+    };
+    let error = serde_json::json!({ "error": { "kind": kind, "message": message } });
+    serde_json::to_vec(&error).expect("a json value always serializes")
+}
+
+fn config_dir() -> io::Result<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+        _ => env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".config"))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "$HOME isn't set")),
+    }
+}
+
+/// Where the manifest goes for every browser that has a config directory.
+fn manifest_paths() -> io::Result<Vec<PathBuf>> {
+    let config_dir = config_dir()?;
+    Ok(BROWSER_DIRS
+        .iter()
+        .map(|browser| config_dir.join(browser))
+        .filter(|dir| dir.is_dir())
+        .map(|dir| {
+            dir.join("NativeMessagingHosts")
+                .join(format!("{}.json", HOST_NAME))
+        })
+        .collect())
+}
+
+/// Write the host manifest allowing the extensions with the given ids to start this binary.
+fn install(extension_ids: &[String]) -> io::Result<()> {
+    if extension_ids.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Usage: spartankey-native-host --install <extension id>...",
+        ));
+    }
+    let paths = manifest_paths()?;
+    if paths.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Neither Chrome nor Chromium has a config directory yet, start the browser once first",
+        ));
+    }
+
+    let manifest = serde_json::json!({
+        "name": HOST_NAME,
+        "description": "Spartan Key",
+        "path": env::current_exe()?.canonicalize()?,
+        "type": "stdio",
+        "allowed_origins": extension_ids
+            .iter()
+            .map(|id| format!("chrome-extension://{}/", id))
+            .collect::<Vec<_>>(),
+    });
+    let manifest = serde_json::to_vec_pretty(&manifest).map_err(io::Error::from)?;
+    for path in paths {
+        fs::create_dir_all(path.parent().expect("the manifest is inside a directory"))?;
+        fs::write(&path, &manifest)?;
+        writeln!(io::stdout(), "Installed {}", path.display())?;
+    }
+    Ok(())
+}
+
+fn uninstall() -> io::Result<()> {
+    for path in manifest_paths()? {
+        match fs::remove_file(&path) {
+            Ok(()) => writeln!(io::stdout(), "Removed {}", path.display())?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,313 @@
+//! Offline lookups in the Have I Been Pwned password list.
+//!
+//! Two kinds of database are supported, both sorted by SHA-1 hash so they can be binary searched
+//! straight from disk without reading them into memory:
+//! - the "SHA-1 ordered by hash" text dump, one `HASH:COUNT` line per password.
+//! - a compact index built from that dump with [build_index], a short header followed by fixed
+//!   size records of the raw 20 byte hash and a big endian `u32` count. It's less than half the
+//!   size of the dump.
+
+use std::{
+    cmp::Ordering,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+
+const INDEX_MAGIC: &[u8; 4] = b"SPKB";
+const INDEX_VERSION: u16 = 1;
+const INDEX_HEADER_LEN: u64 = INDEX_MAGIC.len() as u64 + 2;
+const INDEX_RECORD_LEN: u64 = 20 + 4;
+/// Length of a hash in the text dump, 20 bytes as hex.
+const HEX_HASH_LEN: usize = 40;
+
+pub type PasswordHash = [u8; 20];
+
+/// SHA-1 of `password`, the hash the breach databases are keyed by.
+pub fn hash_password(password: &str) -> PasswordHash {
+    Sha1::digest(password.as_bytes()).into()
+}
+
+/// A login whose password was found in the breach database.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BreachedPassword {
+    pub id: Uuid,
+    /// How often the password appears in the breaches.
+    pub count: u64,
+}
+
+enum Format {
+    Dump,
+    Index,
+}
+
+/// An opened breach database, either a text dump or a compact index.
+pub struct BreachDatabase {
+    reader: BufReader<File>,
+    len: u64,
+    format: Format,
+}
+
+impl BreachDatabase {
+    /// Open the database at `path`, the format is detected from the first bytes of the file.
+    pub fn open(path: &Path) -> AppResult<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let len = reader.get_ref().metadata()?.len();
+
+        let mut header = [0u8; INDEX_HEADER_LEN as usize];
+        let is_index = len >= INDEX_HEADER_LEN
+            && reader.read_exact(&mut header).is_ok()
+            && &header[..INDEX_MAGIC.len()] == INDEX_MAGIC;
+        let format = if is_index {
+            let version = u16::from_le_bytes([header[4], header[5]]);
+            if version != INDEX_VERSION {
+                return Err(AppError::InvalidBreachDatabase(format!(
+                    "Unsupported breach index version {}",
+                    version
+                )));
+            }
+            if (len - INDEX_HEADER_LEN) % INDEX_RECORD_LEN != 0 {
+                return Err(AppError::InvalidBreachDatabase(
+                    "The breach index is truncated".into(),
+                ));
+            }
+            Format::Index
+        } else {
+            Format::Dump
+        };
+
+        Ok(Self {
+            reader,
+            len,
+            format,
+        })
+    }
+
+    /// How often the password with `hash` appears in the breaches, `0` if it doesn't.
+    pub fn lookup(&mut self, hash: &PasswordHash) -> AppResult<u64> {
+        match self.format {
+            Format::Dump => self.lookup_dump(hash),
+            Format::Index => self.lookup_index(hash),
+        }
+    }
+
+    fn lookup_index(&mut self, hash: &PasswordHash) -> AppResult<u64> {
+        let mut low = 0;
+        let mut high = (self.len - INDEX_HEADER_LEN) / INDEX_RECORD_LEN;
+        let mut record = [0u8; INDEX_RECORD_LEN as usize];
+        while low < high {
+            let middle = low + (high - low) / 2;
+            self.reader.seek(SeekFrom::Start(
+                INDEX_HEADER_LEN + middle * INDEX_RECORD_LEN,
+            ))?;
+            self.reader.read_exact(&mut record)?;
+            match record[..20].cmp(hash) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => {
+                    let count = u32::from_be_bytes(record[20..].try_into().unwrap());
+                    return Ok(count.into());
+                }
+            }
+        }
+        Ok(0)
+    }
+
+    /// Binary search over byte offsets. Lines have different lengths, so every probe seeks to
+    /// an offset and compares against the first line starting at or after it.
+    fn lookup_dump(&mut self, hash: &PasswordHash) -> AppResult<u64> {
+        let target = hex_upper(hash);
+        let mut line = Vec::new();
+        // If the hash is in the dump its line starts in `low..high`.
+        let mut low = 0;
+        let mut high = self.len;
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let Some(line_start) = self.read_line_from(middle, &mut line)? else {
+                high = middle;
+                continue;
+            };
+            let (line_hash, count) = parse_dump_line(&line)?;
+            match line_hash.to_ascii_uppercase().as_slice().cmp(&target[..]) {
+                Ordering::Less => low = line_start + line.len() as u64,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return Ok(count),
+            }
+        }
+        Ok(0)
+    }
+
+    /// Read the first line starting at or after `offset` into `line`, including its newline.
+    /// Returns where the line starts, or `None` if no line starts after `offset`.
+    fn read_line_from(&mut self, offset: u64, line: &mut Vec<u8>) -> io::Result<Option<u64>> {
+        line.clear();
+        let mut line_start = offset;
+        if offset > 0 {
+            // Skip the rest of the line `offset` is in, unless it's exactly at a line start.
+            self.reader.seek(SeekFrom::Start(offset - 1))?;
+            line_start += self.reader.read_until(b'\n', line)? as u64 - 1;
+            line.clear();
+        } else {
+            self.reader.seek(SeekFrom::Start(0))?;
+        }
+        if line_start >= self.len || self.reader.read_until(b'\n', line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line_start))
+    }
+}
+
+/// Split a `HASH:COUNT` line of the text dump.
+fn parse_dump_line(line: &[u8]) -> AppResult<(&[u8], u64)> {
+    let invalid = || {
+        AppError::InvalidBreachDatabase(format!(
+            "Expected a `HASH:COUNT` line, found `{}`",
+            String::from_utf8_lossy(line).trim_end()
+        ))
+    };
+    let line = line.trim_ascii_end();
+    if line.len() <= HEX_HASH_LEN || line[HEX_HASH_LEN] != b':' {
+        return Err(invalid());
+    }
+    let (hash, count) = line.split_at(HEX_HASH_LEN);
+    let count = std::str::from_utf8(&count[1..])
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .ok_or_else(invalid)?;
+    Ok((hash, count))
+}
+
+fn hex_upper(bytes: &[u8]) -> Vec<u8> {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    bytes
+        .iter()
+        .flat_map(|byte| [HEX[(byte >> 4) as usize], HEX[(byte & 0xf) as usize]])
+        .collect()
+}
+
+fn parse_hex_hash(hex: &[u8]) -> Option<PasswordHash> {
+    fn nibble(c: u8) -> Option<u8> {
+        (c as char).to_digit(16).map(|digit| digit as u8)
+    }
+    let mut hash = [0u8; 20];
+    for (byte, pair) in hash.iter_mut().zip(hex.chunks_exact(2)) {
+        *byte = nibble(pair[0])? << 4 | nibble(pair[1])?;
+    }
+    Some(hash)
+}
+
+/// Convert the text dump at `dump_path` into a compact index at `index_path`. The dump is read
+/// line by line, counts that don't fit a `u32` are saturated. Returns the number of hashes.
+pub fn build_index(dump_path: &Path, index_path: &Path) -> AppResult<u64> {
+    let dump = BufReader::new(File::open(dump_path)?);
+    let mut index = BufWriter::new(File::create(index_path)?);
+    index.write_all(INDEX_MAGIC)?;
+    index.write_all(&INDEX_VERSION.to_le_bytes())?;
+
+    let mut previous: Option<PasswordHash> = None;
+    let mut hashes = 0;
+    for line in dump.split(b'\n') {
+        let line = line?;
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+        let (hex, count) = parse_dump_line(&line)?;
+        let hash = parse_hex_hash(hex).ok_or_else(|| {
+            AppError::InvalidBreachDatabase(format!(
+                "`{}` isn't a SHA-1 hash",
+                String::from_utf8_lossy(hex)
+            ))
+        })?;
+        if previous.is_some_and(|previous| previous >= hash) {
+            return Err(AppError::InvalidBreachDatabase(
+                "The dump has to be the version ordered by hash".into(),
+            ));
+        }
+        index.write_all(&hash)?;
+        index.write_all(&u32::try_from(count).unwrap_or(u32::MAX).to_be_bytes())?;
+        previous = Some(hash);
+        hashes += 1;
+    }
+    index.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(hashes)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Builds a compact breach index at `index_path` from the ordered-by-hash text dump at
+/// `dump_path`, returns the number of hashes in it.
+pub fn build_breach_index(dump_path: String, index_path: String) -> AppResult<u64> {
+    build_index(Path::new(&dump_path), Path::new(&index_path))
+}
+
+#[test]
+fn test_breach_database() {
+    let passwords = ["password", "123456", "letmein", "qwerty", "dragon"];
+    let mut hashes: Vec<(PasswordHash, u64)> = passwords
+        .iter()
+        .zip(1..)
+        .map(|(password, count)| (hash_password(password), count * 1000))
+        .collect();
+    // Padding with different line lengths between the real hashes.
+    for i in 0..200u32 {
+        hashes.push((hash_password(&format!("padding-{}", i)), u64::from(i)));
+    }
+    hashes.sort();
+    let dump: String = hashes
+        .iter()
+        .map(|(hash, count)| {
+            format!(
+                "{}:{}\r\n",
+                String::from_utf8(hex_upper(hash)).unwrap(),
+                count
+            )
+        })
+        .collect();
+
+    let dir = tempfile::tempdir().unwrap();
+    let dump_path = dir.path().join("pwned-passwords-sha1-ordered-by-hash.txt");
+    let index_path = dir.path().join("pwned.idx");
+    std::fs::write(&dump_path, dump).unwrap();
+    assert_eq!(build_index(&dump_path, &index_path).unwrap(), 205);
+
+    for path in [&dump_path, &index_path] {
+        let mut database = BreachDatabase::open(path).unwrap();
+        for (password, count) in passwords.iter().zip(1..) {
+            assert_eq!(
+                database.lookup(&hash_password(password)).unwrap(),
+                count * 1000
+            );
+        }
+        // The first and last lines are found too.
+        for (hash, count) in [hashes[0], hashes[hashes.len() - 1]] {
+            assert_eq!(database.lookup(&hash).unwrap(), count);
+        }
+        assert_eq!(database.lookup(&hash_password("not pwned")).unwrap(), 0);
+        assert_eq!(database.lookup(&[0; 20]).unwrap(), 0);
+        assert_eq!(database.lookup(&[0xff; 20]).unwrap(), 0);
+    }
+
+    // An unsorted dump is rejected.
+    hashes.swap(0, 1);
+    let unsorted: String = hashes
+        .iter()
+        .map(|(hash, count)| {
+            format!(
+                "{}:{}\n",
+                String::from_utf8(hex_upper(hash)).unwrap(),
+                count
+            )
+        })
+        .collect();
+    std::fs::write(&dump_path, unsorted).unwrap();
+    assert!(matches!(
+        build_index(&dump_path, &index_path),
+        Err(AppError::InvalidBreachDatabase(_))
+    ));
+}
@@ -0,0 +1,106 @@
+//! Logins the browser extension captured from a submitted login form, waiting for the user to
+//! save them. Pending saves only live in memory with their unlocked vault, locking the vault
+//! drops them.
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    entry::unix_timestamp,
+    error::{AppError, AppResult},
+    secret::SecretString,
+};
+
+/// At most this many saves wait for the user, the oldest is dropped to make room.
+const MAX_PENDING_SAVES: usize = 20;
+
+/// What saving a captured login does.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveKind {
+    /// The page has no login with this username yet.
+    New,
+    /// The login `entry` has this username but a different password.
+    Update { entry: Uuid },
+    /// The login `entry` already has this username and password, there's nothing to save.
+    Duplicate { entry: Uuid },
+}
+
+/// What happened to a captured login, `pending` is the id of its save unless it's a duplicate.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capture {
+    pub kind: SaveKind,
+    pub pending: Option<Uuid>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PendingSave {
+    pub id: Uuid,
+    pub kind: SaveKind,
+    pub url: String,
+    pub username: String,
+    pub password: SecretString,
+    /// See [unix_timestamp].
+    pub captured: u64,
+}
+
+impl PendingSave {
+    /// Whether both saves are for the same login, only the latest of them is worth keeping.
+    fn same_login(&self, other: &PendingSave) -> bool {
+        self.username == other.username
+            && match (self.kind, other.kind) {
+                (SaveKind::Update { entry }, SaveKind::Update { entry: other }) => entry == other,
+                _ => self.url == other.url,
+            }
+    }
+}
+
+/// The saves waiting for the user, oldest first.
+#[derive(Debug, Default)]
+pub struct PendingSaves(Vec<PendingSave>);
+
+impl PendingSaves {
+    /// Queue a save, replacing an older one for the same login. Returns the id of the save.
+    pub fn push(
+        &mut self,
+        kind: SaveKind,
+        url: String,
+        username: String,
+        password: SecretString,
+    ) -> Uuid {
+        let save = PendingSave {
+            id: Uuid::new_v4(),
+            kind,
+            url,
+            username,
+            password,
+            captured: unix_timestamp(),
+        };
+        self.0.retain(|pending| !pending.same_login(&save));
+        if self.0.len() >= MAX_PENDING_SAVES {
+            self.0.remove(0);
+        }
+        let id = save.id;
+        self.0.push(save);
+        id
+    }
+
+    pub fn list(&self) -> &[PendingSave] {
+        &self.0
+    }
+
+    pub fn get(&self, id: Uuid) -> AppResult<&PendingSave> {
+        self.0
+            .iter()
+            .find(|save| save.id == id)
+            .ok_or(AppError::UnknownPendingSave(id))
+    }
+
+    pub fn remove(&mut self, id: Uuid) -> AppResult<PendingSave> {
+        let index = self
+            .0
+            .iter()
+            .position(|save| save.id == id)
+            .ok_or(AppError::UnknownPendingSave(id))?;
+        Ok(self.0.remove(index))
+    }
+}
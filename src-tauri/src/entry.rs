@@ -0,0 +1,560 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    otp::OtpSecret,
+    secret::SecretString,
+    strength::{self, Strength},
+    url_match::UrlMatch,
+};
+
+/// How many previous passwords are kept for every login.
+pub const PASSWORD_HISTORY_SIZE: usize = 10;
+
+/// Seconds since the unix epoch, the unit of every timestamp stored in a vault.
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// An extra named value stored with an entry, e.g. a PIN or a security question.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CustomField {
+    pub name: String,
+    pub value: SecretString,
+    /// Hidden fields are masked in the UI like the password.
+    pub hidden: bool,
+}
+
+/// A password a login used before it was changed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PasswordHistoryItem {
+    pub password: SecretString,
+    /// When the password was replaced, see [unix_timestamp].
+    pub replaced: u64,
+}
+
+/// What kind of item an entry is, without its data. Used to filter entries by kind.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    Login,
+    SecureNote,
+    PaymentCard,
+    Identity,
+    ApiKey,
+}
+
+/// The kind specific data of an entry.
+///
+/// New variants must only ever be appended, bincode stores the variant index. The webview sees
+/// the variant name as the only key, e.g. `{ "Login": { "username": ... } }`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum EntryData {
+    Login {
+        username: String,
+        password: SecretString,
+        url: String,
+    },
+    SecureNote {
+        text: SecretString,
+    },
+    PaymentCard {
+        cardholder: String,
+        /// Digits only, spaces and dashes are allowed when it's entered.
+        number: SecretString,
+        expiry_month: u8,
+        /// Four digit year, e.g. `2027`.
+        expiry_year: u16,
+        security_code: SecretString,
+    },
+    Identity {
+        full_name: String,
+        address: String,
+        phone: String,
+        email: String,
+    },
+    ApiKey {
+        service: String,
+        key: SecretString,
+        /// See [unix_timestamp], `None` if the key doesn't expire.
+        expires: Option<u64>,
+    },
+}
+
+impl EntryData {
+    pub fn kind(&self) -> EntryKind {
+        match self {
+            EntryData::Login { .. } => EntryKind::Login,
+            EntryData::SecureNote { .. } => EntryKind::SecureNote,
+            EntryData::PaymentCard { .. } => EntryKind::PaymentCard,
+            EntryData::Identity { .. } => EntryKind::Identity,
+            EntryData::ApiKey { .. } => EntryKind::ApiKey,
+        }
+    }
+
+    /// Check the fields of this kind of entry.
+    pub fn validate(&self) -> AppResult<()> {
+        match self {
+            EntryData::Login { password, url, .. } => {
+                if url.trim().is_empty() {
+                    return Err(invalid("The url can't be empty"));
+                }
+                if password.unsecure().is_empty() {
+                    return Err(invalid("The password can't be empty"));
+                }
+            }
+            EntryData::SecureNote { text } => {
+                if text.unsecure().trim().is_empty() {
+                    return Err(invalid("The note can't be empty"));
+                }
+            }
+            EntryData::PaymentCard {
+                number,
+                expiry_month,
+                expiry_year,
+                security_code,
+                ..
+            } => {
+                let digits = SecretString::from(card_digits(number.unsecure()));
+                if !(12..=19).contains(&digits.unsecure().len())
+                    || !digits.unsecure().bytes().all(|b| b.is_ascii_digit())
+                {
+                    return Err(invalid("The card number must be 12 to 19 digits"));
+                }
+                if !luhn_valid(digits.unsecure()) {
+                    return Err(invalid("The card number isn't valid"));
+                }
+                // Expired cards are allowed on purpose: they're kept around for old statements
+                // and refunds, and a card shouldn't become impossible to edit once it expires.
+                if !(1..=12).contains(expiry_month) {
+                    return Err(invalid("The expiry month must be between 1 and 12"));
+                }
+                if !(2000..=2099).contains(expiry_year) {
+                    return Err(invalid("The expiry year must have four digits"));
+                }
+                let code = security_code.unsecure();
+                if !code.is_empty()
+                    && (!(3..=4).contains(&code.len()) || !code.bytes().all(|b| b.is_ascii_digit()))
+                {
+                    return Err(invalid("The security code must be 3 or 4 digits"));
+                }
+            }
+            EntryData::Identity {
+                full_name, phone, ..
+            } => {
+                if full_name.trim().is_empty() {
+                    return Err(invalid("The name can't be empty"));
+                }
+                if !phone
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || " +-().".contains(c))
+                {
+                    return Err(invalid("The phone number can only contain digits"));
+                }
+            }
+            EntryData::ApiKey { key, .. } => {
+                if key.unsecure().is_empty() {
+                    return Err(invalid("The key can't be empty"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Everything about an entry besides its credentials, sent by the webview when an entry is
+/// added or updated. Missing fields are left empty.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct EntryDetails {
+    pub notes: SecretString,
+    pub custom_fields: Vec<CustomField>,
+    pub tags: Vec<String>,
+    pub favorite: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VaultEntry {
+    /// Random id given to the entry when it's created, also its key in the vault's entries.
+    pub id: Uuid,
+    pub title: String,
+    pub data: EntryData,
+    /// How the url of a login, and its alternative urls, are matched against pages.
+    pub url_match: UrlMatch,
+    /// More urls a login is used on, e.g. the other domains of a single sign-on.
+    pub alternative_urls: Vec<String>,
+    pub notes: SecretString,
+    pub custom_fields: Vec<CustomField>,
+    /// Generates one-time passwords, e.g. the second factor of a login.
+    pub otp: Option<OtpSecret>,
+    pub tags: Vec<String>,
+    pub favorite: bool,
+    /// The folder the entry is in, `None` if it isn't in one.
+    pub folder: Option<Uuid>,
+    /// See [unix_timestamp].
+    pub created: u64,
+    /// Last time the entry was changed by the user.
+    pub modified: u64,
+    /// Last time the entry's credentials were used, `None` if they never were.
+    pub last_used: Option<u64>,
+    /// Previous passwords of a login, most recent first and at most [PASSWORD_HISTORY_SIZE].
+    pub password_history: Vec<PasswordHistoryItem>,
+}
+
+impl VaultEntry {
+    /// A new login with a random id and no details, titled after its url.
+    pub fn new(username: String, password: SecretString, url: String) -> Self {
+        Self::with_data(
+            url.clone(),
+            EntryData::Login {
+                username,
+                password,
+                url,
+            },
+        )
+    }
+
+    /// A new entry of any kind with a random id and no details.
+    pub fn with_data(title: String, data: EntryData) -> Self {
+        let now = unix_timestamp();
+        Self {
+            id: Uuid::new_v4(),
+            title,
+            data,
+            url_match: UrlMatch::default(),
+            alternative_urls: Vec::new(),
+            notes: SecretString::default(),
+            custom_fields: Vec::new(),
+            otp: None,
+            tags: Vec::new(),
+            favorite: false,
+            folder: None,
+            created: now,
+            modified: now,
+            last_used: None,
+            password_history: Vec::new(),
+        }
+    }
+
+    pub fn set_details(&mut self, details: EntryDetails) {
+        self.notes = details.notes;
+        self.custom_fields = details.custom_fields;
+        self.tags = details.tags;
+        self.favorite = details.favorite;
+    }
+
+    pub fn kind(&self) -> EntryKind {
+        self.data.kind()
+    }
+
+    /// The password of a login, `None` for every other kind.
+    pub fn password(&self) -> Option<&SecretString> {
+        match &self.data {
+            EntryData::Login { password, .. } => Some(password),
+            _ => None,
+        }
+    }
+
+    /// When the password was last changed: when the previous one was replaced, or when the entry
+    /// was created if it never was.
+    pub fn password_changed(&self) -> u64 {
+        self.password_history
+            .first()
+            .map(|previous| previous.replaced)
+            .unwrap_or(self.created)
+    }
+
+    /// How hard the password of a login is to guess, `None` for every other kind.
+    pub fn password_strength(&self) -> Option<Strength> {
+        match &self.data {
+            EntryData::Login {
+                username,
+                password,
+                url,
+            } => Some(strength::estimate(
+                password.unsecure(),
+                &[&self.title, username, url],
+            )),
+            _ => None,
+        }
+    }
+
+    /// Replace the title and data, and the details if `details` is `Some`, and bump `modified`.
+    ///
+    /// A login password that's replaced is added to the password history.
+    pub fn update(&mut self, title: String, data: EntryData, details: Option<EntryDetails>) {
+        let old_password = self.password().cloned();
+        self.title = title;
+        self.data = data;
+        if let Some(details) = details {
+            self.set_details(details);
+        }
+        if let Some(old_password) = old_password {
+            if self.password() != Some(&old_password) {
+                self.remember_password(old_password);
+            }
+        }
+        self.modified = unix_timestamp();
+    }
+
+    /// Make the `index`th password of the history (`0` is the most recent) the login's password
+    /// again. The current password is added to the history, so a rollback can be undone.
+    pub fn restore_password(&mut self, index: usize) -> AppResult<()> {
+        if index >= self.password_history.len() {
+            return Err(invalid("There is no such password in the history"));
+        }
+        let EntryData::Login { password, .. } = &mut self.data else {
+            return Err(invalid("The entry isn't a login"));
+        };
+        let restored = self.password_history.remove(index).password;
+        let current = std::mem::replace(password, restored);
+        self.remember_password(current);
+        self.modified = unix_timestamp();
+        Ok(())
+    }
+
+    fn remember_password(&mut self, password: SecretString) {
+        self.password_history.insert(
+            0,
+            PasswordHistoryItem {
+                password,
+                replaced: unix_timestamp(),
+            },
+        );
+        self.password_history.truncate(PASSWORD_HISTORY_SIZE);
+    }
+
+    /// Replace the credentials of a login, see [VaultEntry::update]. A title that was just the
+    /// old url follows the new url.
+    pub fn update_login(
+        &mut self,
+        username: String,
+        password: SecretString,
+        url: String,
+        details: Option<EntryDetails>,
+    ) -> AppResult<()> {
+        let EntryData::Login { url: old_url, .. } = &self.data else {
+            return Err(invalid("The entry isn't a login"));
+        };
+        let title = if self.title == *old_url {
+            url.clone()
+        } else {
+            self.title.clone()
+        };
+        let data = EntryData::Login {
+            username,
+            password,
+            url,
+        };
+        self.update(title, data, details);
+        Ok(())
+    }
+
+    pub fn mark_used(&mut self) {
+        self.last_used = Some(unix_timestamp());
+    }
+
+    /// Check the entry is worth saving. Every entry needs a title and valid data for its kind,
+    /// custom fields need a name, tags can't be blank, the urls of a login must be usable with
+    /// its [UrlMatch] and the OTP secret must be usable.
+    pub fn validate(&self) -> AppResult<()> {
+        if self.title.trim().is_empty() {
+            return Err(invalid("The title can't be empty"));
+        }
+        self.data.validate()?;
+        if self.custom_fields.iter().any(|f| f.name.trim().is_empty()) {
+            return Err(invalid("Custom fields need a name"));
+        }
+        if self.tags.iter().any(|tag| tag.trim().is_empty()) {
+            return Err(invalid("Tags can't be empty"));
+        }
+        match &self.data {
+            EntryData::Login { url, .. } => {
+                if self
+                    .alternative_urls
+                    .iter()
+                    .any(|url| url.trim().is_empty())
+                {
+                    return Err(invalid("Alternative urls can't be empty"));
+                }
+                for url in std::iter::once(url).chain(&self.alternative_urls) {
+                    self.url_match.validate(url)?;
+                }
+            }
+            _ if !self.alternative_urls.is_empty() => {
+                return Err(invalid("Only logins have alternative urls"));
+            }
+            _ => {}
+        }
+        if let Some(otp) = &self.otp {
+            otp.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// An entry as listed to the webview, with the strength score of its password.
+#[derive(Serialize, Debug, Clone)]
+pub struct ListedEntry {
+    #[serde(flatten)]
+    pub entry: VaultEntry,
+    /// `None` for entries without a password.
+    pub password_score: Option<u8>,
+}
+
+impl From<&VaultEntry> for ListedEntry {
+    fn from(entry: &VaultEntry) -> Self {
+        Self {
+            entry: entry.clone(),
+            password_score: entry.password_strength().map(|strength| strength.score),
+        }
+    }
+}
+
+fn invalid(message: &str) -> AppError {
+    AppError::InvalidEntry(message.into())
+}
+
+/// A card number without the spaces and dashes it may have been entered with.
+fn card_digits(number: &str) -> String {
+    number
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect()
+}
+
+/// Luhn checksum of a string of ascii digits.
+fn luhn_valid(digits: &str) -> bool {
+    let mut sum = 0;
+    // Every second digit from the right is doubled.
+    let mut double = false;
+    for digit in digits.bytes().rev().map(|b| (b - b'0') as u32) {
+        sum += match (double, digit * 2) {
+            (false, _) => digit,
+            (true, twice) if twice > 9 => twice - 9,
+            (true, twice) => twice,
+        };
+        double = !double;
+    }
+    sum % 10 == 0
+}
+
+#[test]
+fn test_update_entry() {
+    let mut entry = VaultEntry::new("user".into(), "password".into(), "https://a.com".into());
+    entry.set_details(EntryDetails {
+        tags: vec!["work".into()],
+        favorite: true,
+        ..Default::default()
+    });
+    entry.created = 0;
+    entry.modified = 0;
+
+    // Without details only the credentials change, the title follows the url.
+    entry
+        .update_login("user".into(), "new".into(), "https://b.com".into(), None)
+        .unwrap();
+    assert_eq!(entry.title, "https://b.com");
+    assert!(
+        matches!(&entry.data, EntryData::Login { password, .. } if password.unsecure() == "new")
+    );
+    assert_eq!(entry.tags, vec!["work"]);
+    assert!(entry.favorite);
+    assert_eq!(entry.created, 0);
+    assert!(entry.modified > 0);
+
+    entry
+        .update_login(
+            "user".into(),
+            "new".into(),
+            "https://b.com".into(),
+            Some(EntryDetails::default()),
+        )
+        .unwrap();
+    assert!(entry.tags.is_empty());
+    assert!(!entry.favorite);
+
+    entry.custom_fields.push(CustomField {
+        name: " ".into(),
+        value: "1234".into(),
+        hidden: true,
+    });
+    assert!(matches!(entry.validate(), Err(AppError::InvalidEntry(_))));
+
+    let mut note = VaultEntry::with_data(
+        "note".into(),
+        EntryData::SecureNote {
+            text: "text".into(),
+        },
+    );
+    assert_eq!(note.kind(), EntryKind::SecureNote);
+    assert!(note
+        .update_login("user".into(), "new".into(), "https://b.com".into(), None)
+        .is_err());
+}
+
+#[test]
+fn test_validate_payment_card() {
+    let card = |number: &str, expiry_month: u8, security_code: &str| EntryData::PaymentCard {
+        cardholder: "Jane Doe".into(),
+        number: number.into(),
+        expiry_month,
+        expiry_year: 2030,
+        security_code: security_code.into(),
+    };
+
+    assert!(card("4111 1111 1111 1111", 12, "123").validate().is_ok());
+    assert!(card("4111-1111-1111-1111", 1, "").validate().is_ok());
+    assert!(card("4111 1111 1111 1112", 12, "123").validate().is_err());
+    assert!(card("4111 1111 1111 111a", 12, "123").validate().is_err());
+    assert!(card("4111", 12, "123").validate().is_err());
+    assert!(card("4111 1111 1111 1111", 13, "123").validate().is_err());
+    assert!(card("4111 1111 1111 1111", 12, "12").validate().is_err());
+}
+
+#[test]
+fn test_password_history() {
+    let mut entry = VaultEntry::new("user".into(), "first".into(), "https://a.com".into());
+    for password in ["second", "third"] {
+        entry
+            .update_login("user".into(), password.into(), "https://a.com".into(), None)
+            .unwrap();
+    }
+    // Only password changes are recorded.
+    entry
+        .update_login("other".into(), "third".into(), "https://a.com".into(), None)
+        .unwrap();
+    let history: Vec<_> = entry
+        .password_history
+        .iter()
+        .map(|item| item.password.unsecure())
+        .collect();
+    assert_eq!(history, vec!["second", "first"]);
+
+    entry.restore_password(1).unwrap();
+    assert_eq!(entry.password().unwrap().unsecure(), "first");
+    let history: Vec<_> = entry
+        .password_history
+        .iter()
+        .map(|item| item.password.unsecure())
+        .collect();
+    assert_eq!(history, vec!["third", "second"]);
+    assert!(entry.restore_password(2).is_err());
+
+    for i in 0..PASSWORD_HISTORY_SIZE * 2 {
+        entry
+            .update_login(
+                "user".into(),
+                i.to_string().into(),
+                "https://a.com".into(),
+                None,
+            )
+            .unwrap();
+    }
+    assert_eq!(entry.password_history.len(), PASSWORD_HISTORY_SIZE);
+}
@@ -1,14 +1,18 @@
-use std::fmt::Display;
+use std::{fmt::Display, io};
 
 use aes_gcm::aead;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 use sha2::digest::crypto_common;
+use uuid::Uuid;
 
 pub type EncryptionResult<T> = Result<T, EncryptionError>;
+pub type AppResult<T> = Result<T, AppError>;
 
 #[derive(Debug)]
 pub enum EncryptionError {
     InvalidLength(crypto_common::InvalidLength),
     Aes(aead::Error),
+    Argon2(argon2::Error),
 }
 
 impl Display for EncryptionError {
@@ -18,10 +22,13 @@ impl Display for EncryptionError {
                 f.write_fmt(format_args!("EncryptionError: {}", e))
             }
             EncryptionError::Aes(e) => f.write_fmt(format_args!("EncryptionError: {}", e)),
+            EncryptionError::Argon2(e) => f.write_fmt(format_args!("EncryptionError: {}", e)),
         }
     }
 }
 
+impl std::error::Error for EncryptionError {}
+
 impl From<crypto_common::InvalidLength> for EncryptionError {
     fn from(value: crypto_common::InvalidLength) -> Self {
         Self::InvalidLength(value)
@@ -32,4 +39,177 @@ impl From<aead::Error> for EncryptionError {
     fn from(value: aead::Error) -> Self {
         Self::Aes(value)
     }
-}
\ No newline at end of file
+}
+
+impl From<argon2::Error> for EncryptionError {
+    fn from(value: argon2::Error) -> Self {
+        Self::Argon2(value)
+    }
+}
+
+/// Every error that can be returned from a tauri command.
+///
+/// Serialized to the webview as `{ "kind": "WrongPassword", "message": "Incorrect password" }`,
+/// `kind` is stable and meant to be matched on, `message` is meant to be shown to the user.
+#[derive(Debug)]
+pub enum AppError {
+    Encryption(EncryptionError),
+    Io(io::Error),
+    Bincode(bincode::Error),
+    /// The master password or recovery key couldn't unwrap the vault's internal key. Also returned
+    /// if the salt, KDF parameters or wrapped key in the header were modified, which can't be told
+    /// apart from a wrong password.
+    WrongPassword,
+    /// The recovery key typed in by the user isn't a valid encoding.
+    InvalidRecoveryKey,
+    /// The vault was unlocked, but its entries failed to authenticate. Either the header or the
+    /// ciphertext was modified after the vault was written.
+    Integrity,
+    UnknownVault(String),
+    NoActiveVault,
+    VaultLocked(String),
+    UnknownEntry(Uuid),
+    /// An entry sent by the webview failed validation, the message says why.
+    InvalidEntry(String),
+    UnknownFolder(Uuid),
+    /// A folder operation was rejected, the message says why.
+    InvalidFolder(String),
+    /// A password policy or setting that can't be satisfied, the message says why.
+    InvalidPolicy(String),
+    /// A new master password scored below the configured minimum.
+    WeakPassword {
+        score: u8,
+        minimum: u8,
+    },
+    /// A breach database that isn't a HIBP dump ordered by hash or a valid index.
+    InvalidBreachDatabase(String),
+    /// A request on the local socket that couldn't be understood, the message says why.
+    InvalidRequest(String),
+    /// A local socket client that didn't present the right token.
+    Unauthorized,
+    /// A local socket client speaking a protocol version this app doesn't.
+    UnsupportedProtocol(u32),
+    /// No pairing request with this id is waiting, it was answered or timed out.
+    UnknownPairing(Uuid),
+    /// A vault request from a client that isn't paired or didn't encrypt it.
+    NotPaired,
+    /// No captured login with this id is waiting, it was accepted, dismissed or its vault was
+    /// locked.
+    UnknownPendingSave(Uuid),
+    /// A vault file with a header this app would never write, the message says why.
+    InvalidVault(String),
+}
+
+impl AppError {
+    /// Name of the variant, sent to the webview as the error's `kind`.
+    fn kind(&self) -> &'static str {
+        match self {
+            AppError::Encryption(_) => "Encryption",
+            AppError::Io(_) => "Io",
+            AppError::Bincode(_) => "Bincode",
+            AppError::WrongPassword => "WrongPassword",
+            AppError::InvalidRecoveryKey => "InvalidRecoveryKey",
+            AppError::Integrity => "Integrity",
+            AppError::UnknownVault(_) => "UnknownVault",
+            AppError::NoActiveVault => "NoActiveVault",
+            AppError::VaultLocked(_) => "VaultLocked",
+            AppError::UnknownEntry(_) => "UnknownEntry",
+            AppError::InvalidEntry(_) => "InvalidEntry",
+            AppError::UnknownFolder(_) => "UnknownFolder",
+            AppError::InvalidFolder(_) => "InvalidFolder",
+            AppError::InvalidPolicy(_) => "InvalidPolicy",
+            AppError::WeakPassword { .. } => "WeakPassword",
+            AppError::InvalidBreachDatabase(_) => "InvalidBreachDatabase",
+            AppError::InvalidRequest(_) => "InvalidRequest",
+            AppError::Unauthorized => "Unauthorized",
+            AppError::UnsupportedProtocol(_) => "UnsupportedProtocol",
+            AppError::UnknownPairing(_) => "UnknownPairing",
+            AppError::NotPaired => "NotPaired",
+            AppError::UnknownPendingSave(_) => "UnknownPendingSave",
+            AppError::InvalidVault(_) => "InvalidVault",
+        }
+    }
+}
+
+impl Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Encryption(e) => e.fmt(f),
+            AppError::Io(e) => f.write_fmt(format_args!("IoError: {}", e)),
+            AppError::Bincode(e) => f.write_fmt(format_args!("BincodeError: {}", e)),
+            AppError::WrongPassword => f.write_str("Incorrect password"),
+            AppError::InvalidRecoveryKey => f.write_str("Invalid recovery key"),
+            AppError::Integrity => {
+                f.write_str("The vault file has been tampered with or is corrupted")
+            }
+            AppError::UnknownVault(name) => f.write_fmt(format_args!("No vault named {}", name)),
+            AppError::NoActiveVault => f.write_str("No vault is open"),
+            AppError::VaultLocked(name) => f.write_fmt(format_args!("Vault {} is locked", name)),
+            AppError::UnknownEntry(id) => f.write_fmt(format_args!("No entry with id {}", id)),
+            AppError::InvalidEntry(message) => f.write_str(message),
+            AppError::UnknownFolder(id) => f.write_fmt(format_args!("No folder with id {}", id)),
+            AppError::InvalidFolder(message) => f.write_str(message),
+            AppError::InvalidPolicy(message) => f.write_str(message),
+            AppError::WeakPassword { score, minimum } => f.write_fmt(format_args!(
+                "The master password is too weak, it scores {} out of 4 and at least {} is required",
+                score, minimum
+            )),
+            AppError::InvalidBreachDatabase(message) => f.write_str(message),
+            AppError::InvalidRequest(message) => f.write_str(message),
+            AppError::Unauthorized => f.write_str("Not authorized to use Spartan Key"),
+            AppError::UnsupportedProtocol(version) => f.write_fmt(format_args!(
+                "Protocol version {} isn't supported, this version of Spartan Key speaks version {}",
+                version,
+                crate::protocol::PROTOCOL_VERSION
+            )),
+            AppError::UnknownPairing(id) => {
+                f.write_fmt(format_args!("No pairing request or client with id {}", id))
+            }
+            AppError::NotPaired => {
+                f.write_str("Pair the client with Spartan Key before using the vault")
+            }
+            AppError::UnknownPendingSave(id) => {
+                f.write_fmt(format_args!("No pending save with id {}", id))
+            }
+            AppError::InvalidVault(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl Serialize for AppError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("AppError", 2)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+impl From<EncryptionError> for AppError {
+    fn from(value: EncryptionError) -> Self {
+        Self::Encryption(value)
+    }
+}
+
+impl From<io::Error> for AppError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<bincode::Error> for AppError {
+    fn from(value: bincode::Error) -> Self {
+        Self::Bincode(value)
+    }
+}
+
+#[test]
+fn test_serialize_app_error() {
+    let json = serde_json::to_value(AppError::UnknownVault("work".into())).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({ "kind": "UnknownVault", "message": "No vault named work" })
+    );
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+
+/// A folder inside a vault. Folders are stored flat, the tree is formed by their `parent`s.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Folder {
+    pub id: Uuid,
+    pub name: String,
+    /// `None` for folders at the top of the vault.
+    pub parent: Option<Uuid>,
+}
+
+/// A folder with its subfolders, as sent to the webview.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FolderNode {
+    pub id: Uuid,
+    pub name: String,
+    /// Entries directly in this folder.
+    pub entry_count: usize,
+    /// Entries in this folder and all of its subfolders.
+    pub total_entry_count: usize,
+    /// Sorted by name.
+    pub children: Vec<FolderNode>,
+}
+
+/// Every folder of a vault as a tree.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FolderTree {
+    /// Entries that aren't in any folder.
+    pub unfiled_entry_count: usize,
+    /// The top level folders, sorted by name.
+    pub folders: Vec<FolderNode>,
+}
+
+/// All the folders of a vault, keyed by id.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Folders(HashMap<Uuid, Folder>);
+
+impl Folders {
+    pub fn contains(&self, id: Uuid) -> bool {
+        self.0.contains_key(&id)
+    }
+
+    /// Create a folder called `name` inside `parent` and return its id.
+    pub fn create(&mut self, name: String, parent: Option<Uuid>) -> AppResult<Uuid> {
+        if let Some(parent) = parent {
+            self.get(parent)?;
+        }
+        self.check_name(&name, parent, None)?;
+
+        let id = Uuid::new_v4();
+        self.0.insert(id, Folder { id, name, parent });
+        Ok(id)
+    }
+
+    pub fn rename(&mut self, id: Uuid, name: String) -> AppResult<()> {
+        let parent = self.get(id)?.parent;
+        self.check_name(&name, parent, Some(id))?;
+        self.get_mut(id)?.name = name;
+        Ok(())
+    }
+
+    /// Move the folder `id`, with everything in it, into `parent`.
+    pub fn move_folder(&mut self, id: Uuid, parent: Option<Uuid>) -> AppResult<()> {
+        let name = self.get(id)?.name.clone();
+        if let Some(parent) = parent {
+            self.get(parent)?;
+            if parent == id || self.is_inside(parent, id) {
+                return Err(AppError::InvalidFolder(
+                    "A folder can't be moved into itself".into(),
+                ));
+            }
+        }
+        self.check_name(&name, parent, Some(id))?;
+        self.get_mut(id)?.parent = parent;
+        Ok(())
+    }
+
+    /// Delete the folder `id`. Its subfolders move up into its parent, which is returned so the
+    /// folder's entries can be moved there too.
+    pub fn delete(&mut self, id: Uuid) -> AppResult<Option<Uuid>> {
+        let parent = self.get(id)?.parent;
+        let children: Vec<_> = self
+            .0
+            .values()
+            .filter(|folder| folder.parent == Some(id))
+            .map(|folder| (folder.id, folder.name.clone()))
+            .collect();
+        for (_, name) in &children {
+            self.check_name(name, parent, Some(id))?;
+        }
+
+        self.0.remove(&id);
+        for (child, _) in children {
+            self.get_mut(child)?.parent = parent;
+        }
+        Ok(parent)
+    }
+
+    /// Build the folder tree, `entry_folders` is the folder of every entry in the vault.
+    pub fn tree(&self, entry_folders: impl Iterator<Item = Option<Uuid>>) -> FolderTree {
+        let mut counts: HashMap<Option<Uuid>, usize> = HashMap::new();
+        for folder in entry_folders {
+            *counts.entry(folder).or_default() += 1;
+        }
+        FolderTree {
+            unfiled_entry_count: counts.get(&None).copied().unwrap_or_default(),
+            folders: self.nodes(None, &counts),
+        }
+    }
+
+    fn nodes(
+        &self,
+        parent: Option<Uuid>,
+        counts: &HashMap<Option<Uuid>, usize>,
+    ) -> Vec<FolderNode> {
+        let mut nodes: Vec<_> = self
+            .0
+            .values()
+            .filter(|folder| folder.parent == parent)
+            .map(|folder| {
+                let children = self.nodes(Some(folder.id), counts);
+                let entry_count = counts.get(&Some(folder.id)).copied().unwrap_or_default();
+                FolderNode {
+                    id: folder.id,
+                    name: folder.name.clone(),
+                    entry_count,
+                    total_entry_count: entry_count
+                        + children
+                            .iter()
+                            .map(|child| child.total_entry_count)
+                            .sum::<usize>(),
+                    children,
+                }
+            })
+            .collect();
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+        nodes
+    }
+
+    fn get(&self, id: Uuid) -> AppResult<&Folder> {
+        self.0.get(&id).ok_or(AppError::UnknownFolder(id))
+    }
+
+    fn get_mut(&mut self, id: Uuid) -> AppResult<&mut Folder> {
+        self.0.get_mut(&id).ok_or(AppError::UnknownFolder(id))
+    }
+
+    /// Whether `id` is somewhere below `ancestor`.
+    fn is_inside(&self, id: Uuid, ancestor: Uuid) -> bool {
+        let mut current = self.0.get(&id).and_then(|folder| folder.parent);
+        while let Some(parent) = current {
+            if parent == ancestor {
+                return true;
+            }
+            current = self.0.get(&parent).and_then(|folder| folder.parent);
+        }
+        false
+    }
+
+    /// Folder names can't be blank and have to be unique within their parent. `except` is the
+    /// folder being renamed or moved.
+    fn check_name(&self, name: &str, parent: Option<Uuid>, except: Option<Uuid>) -> AppResult<()> {
+        if name.trim().is_empty() {
+            return Err(AppError::InvalidFolder(
+                "The folder name can't be empty".into(),
+            ));
+        }
+        let taken = self.0.values().any(|folder| {
+            folder.parent == parent && Some(folder.id) != except && folder.name == name
+        });
+        if taken {
+            return Err(AppError::InvalidFolder(format!(
+                "There already is a folder called {}",
+                name
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_folder_tree() {
+    let mut folders = Folders::default();
+    let work = folders.create("Work".into(), None).unwrap();
+    let servers = folders.create("Servers".into(), Some(work)).unwrap();
+    let personal = folders.create("Personal".into(), None).unwrap();
+
+    assert!(matches!(
+        folders.create("Work".into(), None),
+        Err(AppError::InvalidFolder(_))
+    ));
+    assert!(matches!(
+        folders.move_folder(work, Some(servers)),
+        Err(AppError::InvalidFolder(_))
+    ));
+    assert!(matches!(
+        folders.create("x".into(), Some(Uuid::new_v4())),
+        Err(AppError::UnknownFolder(_))
+    ));
+
+    let entries = [None, Some(work), Some(servers), Some(servers)];
+    let tree = folders.tree(entries.into_iter());
+    assert_eq!(tree.unfiled_entry_count, 1);
+    let names: Vec<_> = tree.folders.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, vec!["Personal", "Work"]);
+    assert_eq!(tree.folders[1].entry_count, 1);
+    assert_eq!(tree.folders[1].total_entry_count, 3);
+    assert_eq!(tree.folders[1].children[0].entry_count, 2);
+
+    folders.move_folder(servers, Some(personal)).unwrap();
+    folders.rename(personal, "Home".into()).unwrap();
+    assert_eq!(folders.delete(personal).unwrap(), None);
+    let tree = folders.tree(std::iter::empty());
+    let names: Vec<_> = tree.folders.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, vec!["Servers", "Work"]);
+}
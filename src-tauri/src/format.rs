@@ -0,0 +1,633 @@
+//! On-disk layout of a vault file and the migrations needed to read older layouts.
+//!
+//! Every vault written by this version of Spartan Key looks like this:
+//!
+//! ```text
+//! +-----------+-------------+------------------+-----------+------------+
+//! | magic (4) | version (2) | header (bincode) | nonce(12) | ciphertext |
+//! +-----------+-------------+------------------+-----------+------------+
+//! ```
+//!
+//! The version is a little endian `u16`. The header describes how the vault was encrypted
+//! (KDF, cipher, salts and the wrapped internal key) and is stored in plaintext, the ciphertext
+//! holds the vault entries.
+//!
+//! Since version 2 the magic, version and header bytes are passed to AES-GCM as associated data
+//! when encrypting the entries, so any change to the plaintext header makes decrypting the
+//! entries fail.
+//!
+//! Since version 3 every entry carries a random id, which is also its key in the entries map.
+//! Older vaults keyed their entries by URL, those entries are given a new id when they're read.
+//!
+//! Version 4 added notes, custom fields, tags, the favorite flag and timestamps to the entries.
+//! Entries from older vaults get empty details and are timestamped when they're read.
+//!
+//! Version 5 turned entries into typed items ([EntryData]) with a title. Every entry of an older
+//! vault is a login titled after its url.
+//!
+//! Version 6 added the password history, older entries start with an empty history.
+//!
+//! Version 7 added folders. The ciphertext holds [VaultContents] instead of just the entries and
+//! every entry remembers its folder. Entries of older vaults aren't in any folder.
+//!
+//! Version 8 added one-time password secrets to the entries, older entries don't have one.
+//!
+//! Version 9 added how a login's url is matched against pages and its alternative urls. Logins
+//! of older vaults are matched by registrable domain and have no alternative urls.
+//!
+//! Vaults written before the format was versioned start with a single `0xED` signature byte
+//! instead of the magic bytes and are treated as version `0`.
+//!
+//! When the layout of the header or the entries changes, bump [FORMAT_VERSION], keep the old
+//! structure around in this module and teach [VaultFile::parse] / [deserialize_contents] how to
+//! upgrade it. Vaults are always written using the current version, so older vaults get
+//! upgraded the next time they're saved.
+
+use std::collections::HashMap;
+
+use bincode::ErrorKind;
+use secstr::SecStr;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    entry::{CustomField, EntryData, PasswordHistoryItem, VaultEntry},
+    folder::Folders,
+    kdf::Kdf,
+    otp::OtpSecret,
+    secret::SecretString,
+    url_match::UrlMatch,
+    vault::{NONCE_SIZE, SALT_SIZE},
+};
+
+/// Bytes every versioned vault file starts with.
+pub const MAGIC: [u8; 4] = *b"SPKV";
+
+/// The version written by [serialize_header].
+pub const FORMAT_VERSION: u16 = 9;
+
+/// First version that authenticates the header as associated data of the entries' ciphertext.
+const AUTHENTICATED_HEADER_VERSION: u16 = 2;
+
+/// Signature byte that unversioned (version `0`) vaults start with.
+const LEGACY_SIGNATURE: u8 = 0xED;
+
+/// The cipher used to encrypt both the internal key and the vault entries.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Cipher {
+    #[default]
+    Aes256Gcm,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct VaultHeader {
+    pub kdf: Kdf,
+    pub cipher: Cipher,
+    pub salt: [u8; SALT_SIZE],
+    pub master_password_nonce: [u8; NONCE_SIZE],
+    pub recovery_key_nonce: [u8; NONCE_SIZE],
+    pub master_password_key: Vec<u8>,
+    pub recovery_key: Vec<u8>,
+}
+
+/// Header written by unversioned vaults.
+#[derive(Serialize, Deserialize, Debug)]
+struct VaultHeaderV0 {
+    signature: [u8; 1],
+    salt: [u8; SALT_SIZE],
+    master_password_nonce: [u8; NONCE_SIZE],
+    recovery_key_nonce: [u8; NONCE_SIZE],
+    master_password_key: Vec<u8>,
+    recovery_key: Vec<u8>,
+}
+
+impl From<VaultHeaderV0> for VaultHeader {
+    fn from(value: VaultHeaderV0) -> Self {
+        // Unversioned vaults were always PBKDF2 with 100k iterations and AES256-GCM.
+        Self {
+            kdf: Kdf::Pbkdf2HmacSha256 {
+                iterations: 100_000,
+            },
+            cipher: Cipher::Aes256Gcm,
+            salt: value.salt,
+            master_password_nonce: value.master_password_nonce,
+            recovery_key_nonce: value.recovery_key_nonce,
+            master_password_key: value.master_password_key,
+            recovery_key: value.recovery_key,
+        }
+    }
+}
+
+/// Entry written by vaults before version 3, keyed by its URL.
+#[derive(Serialize, Deserialize, Debug)]
+struct VaultEntryV0 {
+    username: String,
+    password: SecretString,
+    url: String,
+}
+
+impl From<VaultEntryV0> for VaultEntry {
+    fn from(value: VaultEntryV0) -> Self {
+        VaultEntry::new(value.username, value.password, value.url)
+    }
+}
+
+/// Entry written by version 3 vaults, before entries had any details.
+#[derive(Serialize, Deserialize, Debug)]
+struct VaultEntryV1 {
+    id: Uuid,
+    username: String,
+    password: SecretString,
+    url: String,
+}
+
+impl From<VaultEntryV1> for VaultEntry {
+    fn from(value: VaultEntryV1) -> Self {
+        Self {
+            id: value.id,
+            ..VaultEntry::new(value.username, value.password, value.url)
+        }
+    }
+}
+
+/// Entry written by version 4 vaults, when every entry was a login.
+#[derive(Serialize, Deserialize, Debug)]
+struct VaultEntryV2 {
+    id: Uuid,
+    username: String,
+    password: SecretString,
+    url: String,
+    notes: SecretString,
+    custom_fields: Vec<CustomField>,
+    tags: Vec<String>,
+    favorite: bool,
+    created: u64,
+    modified: u64,
+    last_used: Option<u64>,
+}
+
+impl From<VaultEntryV2> for VaultEntry {
+    fn from(value: VaultEntryV2) -> Self {
+        Self {
+            id: value.id,
+            title: value.url.clone(),
+            data: EntryData::Login {
+                username: value.username,
+                password: value.password,
+                url: value.url,
+            },
+            notes: value.notes,
+            custom_fields: value.custom_fields,
+            tags: value.tags,
+            favorite: value.favorite,
+            created: value.created,
+            modified: value.modified,
+            last_used: value.last_used,
+            password_history: Vec::new(),
+            folder: None,
+            otp: None,
+            url_match: UrlMatch::default(),
+            alternative_urls: Vec::new(),
+        }
+    }
+}
+
+/// Entry written by version 5 vaults, before the password history.
+#[derive(Serialize, Deserialize, Debug)]
+struct VaultEntryV3 {
+    id: Uuid,
+    title: String,
+    data: EntryData,
+    notes: SecretString,
+    custom_fields: Vec<CustomField>,
+    tags: Vec<String>,
+    favorite: bool,
+    created: u64,
+    modified: u64,
+    last_used: Option<u64>,
+}
+
+impl From<VaultEntryV3> for VaultEntry {
+    fn from(value: VaultEntryV3) -> Self {
+        Self {
+            id: value.id,
+            title: value.title,
+            data: value.data,
+            notes: value.notes,
+            custom_fields: value.custom_fields,
+            tags: value.tags,
+            favorite: value.favorite,
+            created: value.created,
+            modified: value.modified,
+            last_used: value.last_used,
+            password_history: Vec::new(),
+            folder: None,
+            otp: None,
+            url_match: UrlMatch::default(),
+            alternative_urls: Vec::new(),
+        }
+    }
+}
+
+/// Entry written by version 6 vaults, before folders.
+#[derive(Serialize, Deserialize, Debug)]
+struct VaultEntryV4 {
+    id: Uuid,
+    title: String,
+    data: EntryData,
+    notes: SecretString,
+    custom_fields: Vec<CustomField>,
+    tags: Vec<String>,
+    favorite: bool,
+    created: u64,
+    modified: u64,
+    last_used: Option<u64>,
+    password_history: Vec<PasswordHistoryItem>,
+}
+
+impl From<VaultEntryV4> for VaultEntry {
+    fn from(value: VaultEntryV4) -> Self {
+        Self {
+            id: value.id,
+            title: value.title,
+            data: value.data,
+            notes: value.notes,
+            custom_fields: value.custom_fields,
+            otp: None,
+            url_match: UrlMatch::default(),
+            alternative_urls: Vec::new(),
+            tags: value.tags,
+            favorite: value.favorite,
+            folder: None,
+            created: value.created,
+            modified: value.modified,
+            last_used: value.last_used,
+            password_history: value.password_history,
+        }
+    }
+}
+
+/// Entry written by version 7 vaults, before one-time passwords.
+#[derive(Serialize, Deserialize, Debug)]
+struct VaultEntryV5 {
+    id: Uuid,
+    title: String,
+    data: EntryData,
+    notes: SecretString,
+    custom_fields: Vec<CustomField>,
+    tags: Vec<String>,
+    favorite: bool,
+    folder: Option<Uuid>,
+    created: u64,
+    modified: u64,
+    last_used: Option<u64>,
+    password_history: Vec<PasswordHistoryItem>,
+}
+
+impl From<VaultEntryV5> for VaultEntry {
+    fn from(value: VaultEntryV5) -> Self {
+        Self {
+            id: value.id,
+            title: value.title,
+            data: value.data,
+            notes: value.notes,
+            custom_fields: value.custom_fields,
+            otp: None,
+            url_match: UrlMatch::default(),
+            alternative_urls: Vec::new(),
+            tags: value.tags,
+            favorite: value.favorite,
+            folder: value.folder,
+            created: value.created,
+            modified: value.modified,
+            last_used: value.last_used,
+            password_history: value.password_history,
+        }
+    }
+}
+
+/// Contents written by version 7 vaults, with [VaultEntryV5] entries.
+#[derive(Deserialize, Debug)]
+struct VaultContentsV0 {
+    entries: HashMap<Uuid, VaultEntryV5>,
+    folders: Folders,
+}
+
+/// Entry written by version 8 vaults, before url matching settings.
+#[derive(Serialize, Deserialize, Debug)]
+struct VaultEntryV6 {
+    id: Uuid,
+    title: String,
+    data: EntryData,
+    notes: SecretString,
+    custom_fields: Vec<CustomField>,
+    otp: Option<OtpSecret>,
+    tags: Vec<String>,
+    favorite: bool,
+    folder: Option<Uuid>,
+    created: u64,
+    modified: u64,
+    last_used: Option<u64>,
+    password_history: Vec<PasswordHistoryItem>,
+}
+
+impl From<VaultEntryV6> for VaultEntry {
+    fn from(value: VaultEntryV6) -> Self {
+        Self {
+            id: value.id,
+            title: value.title,
+            data: value.data,
+            url_match: UrlMatch::default(),
+            alternative_urls: Vec::new(),
+            notes: value.notes,
+            custom_fields: value.custom_fields,
+            otp: value.otp,
+            tags: value.tags,
+            favorite: value.favorite,
+            folder: value.folder,
+            created: value.created,
+            modified: value.modified,
+            last_used: value.last_used,
+            password_history: value.password_history,
+        }
+    }
+}
+
+/// Contents written by version 8 vaults, with [VaultEntryV6] entries.
+#[derive(Deserialize, Debug)]
+struct VaultContentsV1 {
+    entries: HashMap<Uuid, VaultEntryV6>,
+    folders: Folders,
+}
+
+/// Everything stored in the ciphertext of a vault.
+#[derive(Deserialize, Debug, Default)]
+pub struct VaultContents {
+    pub entries: HashMap<Uuid, VaultEntry>,
+    pub folders: Folders,
+}
+
+/// Borrowed [VaultContents], so a vault can be written without copying its entries.
+#[derive(Serialize)]
+struct VaultContentsRef<'a> {
+    entries: &'a HashMap<Uuid, VaultEntry>,
+    folders: &'a Folders,
+}
+
+/// A parsed, but still encrypted, vault file.
+#[derive(Debug)]
+pub struct VaultFile {
+    /// The version the file was written with, [FORMAT_VERSION] for new files.
+    pub version: u16,
+    pub header: VaultHeader,
+    /// Everything before the nonce, exactly as it was read from disk.
+    header_bytes: Vec<u8>,
+    pub nonce: [u8; NONCE_SIZE],
+    pub ciphertext: Vec<u8>,
+}
+
+impl VaultFile {
+    /// Parse the bytes of a vault file of any known version, upgrading the header to the
+    /// current [VaultHeader] layout.
+    pub fn parse(bytes: &[u8]) -> bincode::Result<Self> {
+        let (version, mut rest) = if bytes.starts_with(&MAGIC) {
+            let version_bytes = bytes
+                .get(MAGIC.len()..MAGIC.len() + 2)
+                .ok_or_else(|| custom_error("Vault file is truncated"))?;
+            let version = u16::from_le_bytes([version_bytes[0], version_bytes[1]]);
+            (version, &bytes[MAGIC.len() + 2..])
+        } else if bytes.first() == Some(&LEGACY_SIGNATURE) {
+            (0, bytes)
+        } else {
+            return Err(custom_error("Not a Spartan Key vault"));
+        };
+
+        let header = match version {
+            0 => bincode::deserialize_from::<_, VaultHeaderV0>(&mut rest)?.into(),
+            1..=9 => bincode::deserialize_from(&mut rest)?,
+            _ => return Err(unsupported_version(version)),
+        };
+        let header_bytes = bytes[..bytes.len() - rest.len()].to_vec();
+        let nonce: [u8; NONCE_SIZE] = bincode::deserialize_from(&mut rest)?;
+
+        Ok(Self {
+            version,
+            header,
+            header_bytes,
+            nonce,
+            ciphertext: rest.to_vec(),
+        })
+    }
+
+    /// The associated data the entries were encrypted with, empty for versions that didn't
+    /// authenticate the header yet.
+    pub fn associated_data(&self) -> &[u8] {
+        if self.version >= AUTHENTICATED_HEADER_VERSION {
+            &self.header_bytes
+        } else {
+            &[]
+        }
+    }
+}
+
+/// Serialize the magic, current [FORMAT_VERSION] and `header`. The entries' nonce and ciphertext
+/// follow these bytes in the file, and the bytes are used as the associated data of that ciphertext.
+pub fn serialize_header(header: &VaultHeader) -> bincode::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(128);
+    bytes.extend(MAGIC);
+    bytes.extend(FORMAT_VERSION.to_le_bytes());
+    bytes.extend(bincode::serialize(header)?);
+    Ok(bytes)
+}
+
+/// Deserialize the decrypted contents written by a vault of the given `version`.
+pub fn deserialize_contents(version: u16, bytes: &[u8]) -> bincode::Result<VaultContents> {
+    let entries = match version {
+        0..=2 => {
+            let entries: HashMap<String, VaultEntryV0> = bincode::deserialize(bytes)?;
+            entries
+                .into_values()
+                .map(|entry| {
+                    let entry = VaultEntry::from(entry);
+                    (entry.id, entry)
+                })
+                .collect()
+        }
+        3 => upgrade_entries::<VaultEntryV1>(bytes)?,
+        4 => upgrade_entries::<VaultEntryV2>(bytes)?,
+        5 => upgrade_entries::<VaultEntryV3>(bytes)?,
+        6 => upgrade_entries::<VaultEntryV4>(bytes)?,
+        7 => {
+            let contents: VaultContentsV0 = bincode::deserialize(bytes)?;
+            return Ok(VaultContents {
+                entries: contents
+                    .entries
+                    .into_iter()
+                    .map(|(id, entry)| (id, entry.into()))
+                    .collect(),
+                folders: contents.folders,
+            });
+        }
+        8 => {
+            let contents: VaultContentsV1 = bincode::deserialize(bytes)?;
+            return Ok(VaultContents {
+                entries: contents
+                    .entries
+                    .into_iter()
+                    .map(|(id, entry)| (id, entry.into()))
+                    .collect(),
+                folders: contents.folders,
+            });
+        }
+        9 => return bincode::deserialize(bytes),
+        _ => return Err(unsupported_version(version)),
+    };
+    Ok(VaultContents {
+        entries,
+        ..Default::default()
+    })
+}
+
+/// Serialize the vault entries and folders using the current [FORMAT_VERSION].
+///
+/// The buffer is allocated with its final size up front, so growing it doesn't leave copies of
+/// the plaintext behind.
+pub fn serialize_contents(
+    entries: &HashMap<Uuid, VaultEntry>,
+    folders: &Folders,
+) -> bincode::Result<SecStr> {
+    let contents = VaultContentsRef { entries, folders };
+    let mut bytes = Vec::with_capacity(bincode::serialized_size(&contents)? as usize);
+    bincode::serialize_into(&mut bytes, &contents)?;
+    Ok(SecStr::new(bytes))
+}
+
+/// Deserialize the entries map of a version that stored an older entry layout and upgrade them.
+fn upgrade_entries<E>(bytes: &[u8]) -> bincode::Result<HashMap<Uuid, VaultEntry>>
+where
+    E: DeserializeOwned + Into<VaultEntry>,
+{
+    let entries: HashMap<Uuid, E> = bincode::deserialize(bytes)?;
+    Ok(entries
+        .into_iter()
+        .map(|(id, entry)| (id, entry.into()))
+        .collect())
+}
+
+fn unsupported_version(version: u16) -> Box<ErrorKind> {
+    custom_error(&format!(
+        "Vault format version {} is newer than this version of Spartan Key supports ({})",
+        version, FORMAT_VERSION
+    ))
+}
+
+fn custom_error(message: &str) -> Box<ErrorKind> {
+    Box::new(ErrorKind::Custom(message.into()))
+}
+
+#[test]
+fn test_parse_legacy_header() {
+    let legacy = VaultHeaderV0 {
+        signature: [LEGACY_SIGNATURE],
+        salt: [1; SALT_SIZE],
+        master_password_nonce: [2; NONCE_SIZE],
+        recovery_key_nonce: [3; NONCE_SIZE],
+        master_password_key: vec![4; 48],
+        recovery_key: vec![5; 48],
+    };
+    let mut bytes = bincode::serialize(&legacy).unwrap();
+    bytes.extend([6; NONCE_SIZE]);
+    bytes.extend([7; 32]);
+
+    let file = VaultFile::parse(&bytes).unwrap();
+    assert_eq!(file.version, 0);
+    assert_eq!(
+        file.header.kdf,
+        Kdf::Pbkdf2HmacSha256 {
+            iterations: 100_000
+        }
+    );
+    assert_eq!(file.header.salt, legacy.salt);
+    assert_eq!(file.header.master_password_key, legacy.master_password_key);
+    assert_eq!(file.nonce, [6; NONCE_SIZE]);
+    assert_eq!(file.ciphertext, vec![7; 32]);
+    assert!(file.associated_data().is_empty());
+}
+
+#[test]
+fn test_parse_current_header() {
+    let header = VaultHeader {
+        salt: [1; SALT_SIZE],
+        master_password_key: vec![4; 48],
+        ..Default::default()
+    };
+    let mut bytes = serialize_header(&header).unwrap();
+    let header_len = bytes.len();
+    bytes.extend([6; NONCE_SIZE]);
+    bytes.extend([7; 32]);
+
+    let file = VaultFile::parse(&bytes).unwrap();
+    assert_eq!(file.version, FORMAT_VERSION);
+    assert_eq!(file.header.salt, header.salt);
+    assert_eq!(file.associated_data(), &bytes[..header_len]);
+    assert_eq!(file.nonce, [6; NONCE_SIZE]);
+    assert_eq!(file.ciphertext, vec![7; 32]);
+}
+
+#[test]
+fn test_migrate_legacy_entries() {
+    let legacy: HashMap<String, VaultEntryV0> = ["https://a.com", "https://b.com"]
+        .into_iter()
+        .map(|url| {
+            let entry = VaultEntryV0 {
+                username: "user".into(),
+                password: "password".into(),
+                url: url.into(),
+            };
+            (url.to_string(), entry)
+        })
+        .collect();
+    let bytes = bincode::serialize(&legacy).unwrap();
+
+    let entries = deserialize_contents(2, &bytes).unwrap().entries;
+    assert_eq!(entries.len(), 2);
+    assert!(entries.iter().all(|(id, entry)| *id == entry.id));
+
+    // Written back using the current version the ids stay the same.
+    let bytes = serialize_contents(&entries, &Folders::default()).unwrap();
+    let read = deserialize_contents(FORMAT_VERSION, bytes.unsecure())
+        .unwrap()
+        .entries;
+    let mut ids: Vec<_> = read.keys().collect();
+    let mut expected: Vec<_> = entries.keys().collect();
+    ids.sort();
+    expected.sort();
+    assert_eq!(ids, expected);
+}
+
+#[test]
+fn test_migrate_v1_entries() {
+    let id = Uuid::new_v4();
+    let legacy = HashMap::from([(
+        id,
+        VaultEntryV1 {
+            id,
+            username: "user".into(),
+            password: "password".into(),
+            url: "https://a.com".into(),
+        },
+    )]);
+    let bytes = bincode::serialize(&legacy).unwrap();
+
+    let entries = deserialize_contents(3, &bytes).unwrap().entries;
+    let entry = &entries[&id];
+    assert_eq!(entry.id, id);
+    assert!(matches!(
+        &entry.data,
+        EntryData::Login { password, .. } if password.unsecure() == "password"
+    ));
+    assert_eq!(entry.title, "https://a.com");
+    assert!(entry.tags.is_empty());
+    assert!(entry.created > 0);
+    assert_eq!(entry.last_used, None);
+}
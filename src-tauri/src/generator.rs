@@ -0,0 +1,387 @@
+//! Random password and passphrase generation.
+
+use std::{fs, path::PathBuf};
+
+use rand::{rngs::OsRng, seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{AppError, AppResult},
+    secret::SecretString,
+};
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!@#$%^&*";
+/// Characters that are easily mistaken for one another in most fonts.
+const LOOKALIKES: &str = "Il1|O0o";
+/// Passwords longer than this are almost certainly a typo in the policy.
+const MAX_LENGTH: usize = 1024;
+/// The same for passphrases.
+const MAX_WORDS: usize = 64;
+/// The EFF large word list of 7776 words, as published at
+/// https://www.eff.org/files/2016/07/18/eff_large_wordlist.txt.
+pub(crate) const EFF_WORD_LIST: &str = include_str!("../resources/eff_large_wordlist.txt");
+
+/// What a generated password has to look like.
+///
+/// Every character class is a minimum count, `None` leaves the class out of the password
+/// entirely. `Some(0)` allows the class without requiring it.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    pub length: usize,
+    pub lowercase: Option<usize>,
+    pub uppercase: Option<usize>,
+    pub digits: Option<usize>,
+    pub symbols: Option<usize>,
+    /// Leave out characters like `l`, `1`, `O` and `0`.
+    pub exclude_lookalikes: bool,
+    /// Replaces the default symbols, duplicates are ignored.
+    pub symbol_set: Option<String>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            length: 20,
+            lowercase: Some(1),
+            uppercase: Some(1),
+            digits: Some(1),
+            symbols: Some(1),
+            exclude_lookalikes: false,
+            symbol_set: None,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// The characters of every enabled class with their minimum counts, after look-alikes and
+    /// duplicates are removed.
+    fn classes(&self) -> AppResult<Vec<(Vec<char>, usize)>> {
+        let symbols = self.symbol_set.as_deref().unwrap_or(SYMBOLS);
+        if symbols
+            .chars()
+            .any(|c| c.is_alphanumeric() || c.is_whitespace())
+        {
+            return Err(AppError::InvalidPolicy(
+                "Symbols can't contain letters, digits or spaces".into(),
+            ));
+        }
+
+        let mut classes = Vec::new();
+        for (chars, min) in [
+            (LOWERCASE, self.lowercase),
+            (UPPERCASE, self.uppercase),
+            (DIGITS, self.digits),
+            (symbols, self.symbols),
+        ] {
+            let Some(min) = min else { continue };
+            let mut class: Vec<char> = Vec::new();
+            for c in chars.chars() {
+                let excluded = self.exclude_lookalikes && LOOKALIKES.contains(c);
+                if !excluded && !class.contains(&c) {
+                    class.push(c);
+                }
+            }
+            if class.is_empty() {
+                return Err(AppError::InvalidPolicy(
+                    "An enabled character class has no characters".into(),
+                ));
+            }
+            classes.push((class, min));
+        }
+
+        if classes.is_empty() {
+            return Err(AppError::InvalidPolicy(
+                "At least one character class has to be enabled".into(),
+            ));
+        }
+        if self.length == 0 || self.length > MAX_LENGTH {
+            return Err(AppError::InvalidPolicy(format!(
+                "The length has to be between 1 and {}",
+                MAX_LENGTH
+            )));
+        }
+        let required: usize = classes.iter().map(|(_, min)| min).sum();
+        if required > self.length {
+            return Err(AppError::InvalidPolicy(format!(
+                "The minimum counts add up to {}, more than the length of {}",
+                required, self.length
+            )));
+        }
+        Ok(classes)
+    }
+}
+
+/// A password fresh out of the generator.
+#[derive(Serialize, Debug)]
+pub struct GeneratedPassword {
+    pub password: SecretString,
+    /// In bits.
+    pub entropy: f64,
+}
+
+/// Generate a password that satisfies `policy`.
+///
+/// Every character is drawn uniformly from the OS' cryptographically secure RNG. The minimum
+/// count of each class is drawn from that class, the rest from all enabled classes combined and
+/// the result is shuffled so the required characters don't sit at the front.
+///
+/// The entropy is that of a uniformly random string of the same length over all of the policy's
+/// characters. The minimum counts lower the real value slightly, by far less than a bit for
+/// sensible policies.
+pub fn generate_password_with(policy: &PasswordPolicy) -> AppResult<GeneratedPassword> {
+    let classes = policy.classes()?;
+    let pool: Vec<char> = classes
+        .iter()
+        .flat_map(|(class, _)| class)
+        .copied()
+        .collect();
+
+    let mut chars = Vec::with_capacity(policy.length);
+    for (class, min) in &classes {
+        for _ in 0..*min {
+            chars.push(*class.choose(&mut OsRng).unwrap());
+        }
+    }
+    while chars.len() < policy.length {
+        chars.push(*pool.choose(&mut OsRng).unwrap());
+    }
+    chars.shuffle(&mut OsRng);
+
+    let password: String = chars.iter().collect();
+    chars.fill('\0');
+    Ok(GeneratedPassword {
+        password: password.into(),
+        entropy: policy.length as f64 * (pool.len() as f64).log2(),
+    })
+}
+
+/// What a generated passphrase has to look like.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct PassphrasePolicy {
+    pub word_count: usize,
+    pub separator: String,
+    /// Start every word with a capital letter.
+    pub capitalize: bool,
+    /// Append a random digit to one random word.
+    pub include_digit: bool,
+    /// A word list file to use instead of the EFF large word list. Either one word per line or
+    /// in the EFF's dice format (`11111\tabacus`).
+    pub word_list: Option<PathBuf>,
+}
+
+impl Default for PassphrasePolicy {
+    fn default() -> Self {
+        Self {
+            word_count: 6,
+            separator: "-".into(),
+            capitalize: false,
+            include_digit: false,
+            word_list: None,
+        }
+    }
+}
+
+/// The distinct words of a word list. Only the last column of every line is used, which skips
+/// the dice numbers of diceware style lists.
+fn parse_word_list(text: &str) -> AppResult<Vec<&str>> {
+    let mut words: Vec<&str> = text
+        .lines()
+        .filter_map(|line| line.split_whitespace().last())
+        .collect();
+    words.sort_unstable();
+    words.dedup();
+    if words.len() < 2 {
+        return Err(AppError::InvalidPolicy(
+            "The word list needs at least two different words".into(),
+        ));
+    }
+    Ok(words)
+}
+
+/// Generate a passphrase that satisfies `policy`.
+///
+/// Words are drawn uniformly from the OS' cryptographically secure RNG. Capitalizing doesn't add
+/// any entropy, the digit adds the entropy of the digit itself and of the word it's appended to.
+pub fn generate_passphrase_with(policy: &PassphrasePolicy) -> AppResult<GeneratedPassword> {
+    if policy.word_count == 0 || policy.word_count > MAX_WORDS {
+        return Err(AppError::InvalidPolicy(format!(
+            "The word count has to be between 1 and {}",
+            MAX_WORDS
+        )));
+    }
+    let custom_list = match &policy.word_list {
+        Some(path) => Some(fs::read_to_string(path)?),
+        None => None,
+    };
+    let words = parse_word_list(custom_list.as_deref().unwrap_or(EFF_WORD_LIST))?;
+
+    let digit_word = policy
+        .include_digit
+        .then(|| OsRng.gen_range(0..policy.word_count));
+    let mut passphrase = String::new();
+    for i in 0..policy.word_count {
+        if i > 0 {
+            passphrase.push_str(&policy.separator);
+        }
+        let word = words.choose(&mut OsRng).unwrap();
+        let mut chars = word.chars();
+        if let (true, Some(first)) = (policy.capitalize, chars.next()) {
+            passphrase.extend(first.to_uppercase());
+            passphrase.push_str(chars.as_str());
+        } else {
+            passphrase.push_str(word);
+        }
+        if digit_word == Some(i) {
+            passphrase.push(char::from(b'0' + OsRng.gen_range(0..10)));
+        }
+    }
+
+    let mut entropy = policy.word_count as f64 * (words.len() as f64).log2();
+    if policy.include_digit {
+        entropy += 10f64.log2() + (policy.word_count as f64).log2();
+    }
+    Ok(GeneratedPassword {
+        password: passphrase.into(),
+        entropy,
+    })
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Generate a random password, the default policy is used if `policy` is omitted.
+pub fn generate_password(policy: Option<PasswordPolicy>) -> AppResult<GeneratedPassword> {
+    generate_password_with(&policy.unwrap_or_default())
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Generate a random passphrase, the default policy is used if `policy` is omitted.
+pub fn generate_passphrase(policy: Option<PassphrasePolicy>) -> AppResult<GeneratedPassword> {
+    generate_passphrase_with(&policy.unwrap_or_default())
+}
+
+#[test]
+fn test_generate_password() {
+    let policy = PasswordPolicy {
+        length: 6,
+        lowercase: None,
+        uppercase: Some(2),
+        digits: Some(4),
+        symbols: None,
+        exclude_lookalikes: true,
+        symbol_set: None,
+    };
+    for _ in 0..50 {
+        let generated = generate_password_with(&policy).unwrap();
+        let password = generated.password.unsecure();
+        assert_eq!(password.chars().count(), 6);
+        assert_eq!(password.chars().filter(char::is_ascii_digit).count(), 4);
+        assert!(password.chars().all(|c| !LOOKALIKES.contains(c)));
+        // 24 uppercase letters and 8 digits are left.
+        assert_eq!(generated.entropy, 6.0 * 32f64.log2());
+    }
+
+    // The old generator panicked on anything shorter than 5 characters.
+    assert_eq!(
+        generate_password_with(&PasswordPolicy {
+            length: 1,
+            lowercase: Some(0),
+            uppercase: None,
+            digits: None,
+            symbols: None,
+            ..Default::default()
+        })
+        .unwrap()
+        .password
+        .unsecure()
+        .len(),
+        1
+    );
+
+    let custom = PasswordPolicy {
+        symbol_set: Some("--_".into()),
+        symbols: Some(20),
+        lowercase: None,
+        uppercase: None,
+        digits: None,
+        ..Default::default()
+    };
+    let generated = generate_password_with(&custom).unwrap();
+    assert!(generated
+        .password
+        .unsecure()
+        .chars()
+        .all(|c| c == '-' || c == '_'));
+    assert_eq!(generated.entropy, 20.0);
+
+    for policy in [
+        PasswordPolicy {
+            length: 3,
+            ..Default::default()
+        },
+        PasswordPolicy {
+            symbol_set: Some("abc".into()),
+            ..Default::default()
+        },
+        PasswordPolicy {
+            lowercase: None,
+            uppercase: None,
+            digits: None,
+            symbols: None,
+            ..Default::default()
+        },
+    ] {
+        assert!(matches!(
+            generate_password_with(&policy),
+            Err(AppError::InvalidPolicy(_))
+        ));
+    }
+}
+
+#[test]
+fn test_generate_passphrase() {
+    let words = parse_word_list(EFF_WORD_LIST).unwrap();
+    assert_eq!(words.len(), 7776);
+
+    let generated = generate_passphrase_with(&PassphrasePolicy::default()).unwrap();
+    let passphrase: Vec<_> = generated.password.unsecure().split('-').collect();
+    assert_eq!(passphrase.len(), 6);
+    assert!(passphrase.iter().all(|word| words.contains(word)));
+    assert_eq!(generated.entropy, 6.0 * (words.len() as f64).log2());
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("words.txt");
+    fs::write(&path, "11111\tabacus\n11112\tabdomen\n\n11113\tabacus\n").unwrap();
+    let generated = generate_passphrase_with(&PassphrasePolicy {
+        word_count: 3,
+        separator: " ".into(),
+        capitalize: true,
+        include_digit: true,
+        word_list: Some(path.clone()),
+    })
+    .unwrap();
+    let passphrase = generated.password.unsecure();
+    assert_eq!(passphrase.split(' ').count(), 3);
+    assert!(passphrase
+        .split(' ')
+        .all(|word| word.starts_with("Abacus") || word.starts_with("Abdomen")));
+    assert_eq!(passphrase.chars().filter(char::is_ascii_digit).count(), 1);
+    assert_eq!(generated.entropy, 3.0 + 10f64.log2() + 3f64.log2());
+
+    assert!(matches!(
+        generate_passphrase_with(&PassphrasePolicy {
+            word_count: 0,
+            ..Default::default()
+        }),
+        Err(AppError::InvalidPolicy(_))
+    ));
+    assert!(matches!(
+        parse_word_list("only\nonly"),
+        Err(AppError::InvalidPolicy(_))
+    ));
+}
@@ -0,0 +1,661 @@
+//! Local socket external clients (the native messaging host, a CLI or scripts) use to reach the
+//! unlocked vault of the running app, see [crate::protocol] for the framing.
+//!
+//! Every connection starts with a hello naming the protocol version the client speaks and the
+//! token written to [token_path] when the app started, which only the user running the app can
+//! read: `{ "type": "hello", "version": 3, "token": "..." }` is answered with
+//! `{ "hello": { "version": 3 } }`. A wrong token closes the connection.
+//!
+//! After that every request gets exactly one response, e.g. `{ "lock_state": {...} }` or
+//! `{ "error": { "kind": ..., "message": ... } }` like the errors of tauri commands.
+//!
+//! Requests that read or write the vault, e.g. `{ "type": "list_matches", "url": "..." }`, are
+//! only answered for a paired client inside an encrypted session, see [crate::pairing]:
+//! - `{ "type": "pair_start", "name": "Chromium", "public_key": "<hex>" }` is answered with
+//!   `{ "pairing": { "id": ..., "public_key": "<hex>" } }` and the app asks the user to compare
+//!   the confirmation codes. Once they confirm, `id` is the client's id.
+//! - `{ "type": "open_session", "client_id": ..., "nonce": "<hex>" }` is answered with
+//!   `{ "session": { "nonce": "<hex>" } }`.
+//! - `{ "type": "encrypted", "ciphertext": "<hex>" }` carries an encrypted request and is
+//!   answered with `{ "encrypted": { "ciphertext": "<hex>" } }` carrying the encrypted response.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::{
+        fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::Arc,
+    thread,
+};
+
+use rand::{rngs::OsRng, RngCore};
+use secstr::SecStr;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, Wry};
+use uuid::Uuid;
+
+use crate::{
+    capture::Capture,
+    entry::{unix_timestamp, EntryData},
+    error::{AppError, AppResult},
+    otp::{OtpKind, TotpCode},
+    pairing::{PairingState, SessionCipher},
+    protocol::{
+        read_message, socket_dir, socket_path, token_path, write_message, PROTOCOL_VERSION,
+    },
+    secret::SecretString,
+    state::ConfigState,
+    vault::{VaultManager, VaultManagerState},
+};
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Hello {
+        version: u32,
+        token: SecretString,
+    },
+    /// Start pairing a client with its X25519 public key, hex encoded.
+    PairStart {
+        name: String,
+        public_key: String,
+    },
+    /// Start an encrypted session of a paired client with its hex encoded nonce.
+    OpenSession {
+        client_id: Uuid,
+        nonce: String,
+    },
+    /// A [VaultRequest] encrypted with the session key, hex encoded.
+    Encrypted {
+        ciphertext: String,
+    },
+    /// See [VaultRequest::GetLockState], answered without a session.
+    GetLockState,
+    /// See [VaultRequest::Lock], answered without a session.
+    Lock,
+}
+
+/// Requests that read or write the vault, only answered inside an encrypted session except for
+/// the lock state and locking.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum VaultRequest {
+    /// The logins of the active vault for the page at `url`, without their passwords.
+    ListMatches {
+        url: String,
+    },
+    /// The credentials of the login `id`, which is marked as used.
+    GetSecret {
+        id: Uuid,
+    },
+    /// A login form was submitted on the page at `url`, queue the login for the user to save.
+    CaptureLogin {
+        url: String,
+        username: String,
+        password: SecretString,
+    },
+    GetLockState,
+    /// Lock the active vault, like the lock button in the app.
+    Lock,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Hello { version: u32 },
+    Pairing { id: Uuid, public_key: String },
+    Session { nonce: String },
+    Encrypted { ciphertext: String },
+    Matches(Vec<Match>),
+    Secret(Secret),
+    Captured(Capture),
+    LockState(LockState),
+    Error(AppError),
+}
+
+/// A login matching a page, without its password.
+#[derive(Serialize, Debug)]
+struct Match {
+    id: Uuid,
+    title: String,
+    username: String,
+    url: String,
+}
+
+#[derive(Serialize, Debug)]
+struct Secret {
+    id: Uuid,
+    username: String,
+    password: SecretString,
+    /// The current code if the login has a TOTP secret.
+    totp: Option<TotpCode>,
+}
+
+#[derive(Serialize, Debug)]
+struct LockState {
+    /// `None` if no vault was opened.
+    vault: Option<String>,
+    unlocked: bool,
+}
+
+/// Start listening on [socket_path] in the background and write a new token to [token_path].
+/// Fails if another instance of the app is already listening.
+pub fn start(app_handle: AppHandle<Wry>) -> io::Result<()> {
+    create_private_dir(&socket_dir())?;
+    let path = socket_path();
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "Spartan Key is already running",
+            ));
+        }
+        // Left behind by an instance that didn't shut down cleanly.
+        fs::remove_file(&path)?;
+    }
+    let token = Arc::new(write_token()?);
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let app_handle = app_handle.clone();
+                    let token = token.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve(stream, &token, &app_handle) {
+                            eprintln!("Error serving local socket client: {}", e);
+                        }
+                    });
+                }
+                Err(e) => eprintln!("Error accepting local socket client: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Create `dir` so only the current user can get into it, the socket is reachable as soon as it's
+/// bound so its permissions can't be relied on. A directory that already exists must be owned by
+/// the current user and closed to everybody else, in the shared temporary directory anyone could
+/// have created it first.
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    let metadata = fs::symlink_metadata(dir)?;
+    // SAFETY: getuid can't fail and has no preconditions.
+    let uid = unsafe { libc::getuid() };
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} must be a directory only the current user can access",
+                dir.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// A random token only readable by the current user, replacing the one of the previous run.
+fn write_token() -> io::Result<SecretString> {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token = SecretString::from(
+        bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>(),
+    );
+    bytes.fill(0);
+
+    let path = token_path();
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)?;
+    file.write_all(token.unsecure().as_bytes())?;
+    Ok(token)
+}
+
+/// The app state requests are answered from.
+struct AppState<'a> {
+    vault_manager: &'a VaultManagerState,
+    config: &'a ConfigState,
+    pairings: &'a PairingState,
+}
+
+/// Answer requests until the client disconnects or fails to authenticate.
+fn serve(
+    mut stream: UnixStream,
+    token: &SecretString,
+    app_handle: &AppHandle<Wry>,
+) -> io::Result<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let config_state: tauri::State<ConfigState> = app_handle.state();
+    let pairing_state: tauri::State<PairingState> = app_handle.state();
+    let state = AppState {
+        vault_manager: &vault_manager_state,
+        config: &config_state,
+        pairings: &pairing_state,
+    };
+
+    let mut session = Session::new(token);
+    while let Some(message) = read_message(&mut stream)? {
+        let response = session.respond(&message, &state);
+        let bytes = serde_json::to_vec(&response).map_err(io::Error::from)?;
+        write_message(&mut stream, &bytes)?;
+        if matches!(response, Response::Error(AppError::Unauthorized)) && !session.authenticated {
+            break;
+        }
+        if let Response::Pairing { id, .. } = response {
+            // The webview shows the code for the user to compare with the client's.
+            if let Some(request) = pairing_state.request(id) {
+                if let Err(e) = app_handle.emit_all("pairing-requested", request) {
+                    eprintln!("Error announcing pairing request: {}", e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What a connection has done so far.
+struct Session<'a> {
+    token: &'a SecretString,
+    authenticated: bool,
+    /// Set once a paired client opened an encrypted session.
+    cipher: Option<SessionCipher>,
+}
+
+impl<'a> Session<'a> {
+    fn new(token: &'a SecretString) -> Self {
+        Self {
+            token,
+            authenticated: false,
+            cipher: None,
+        }
+    }
+
+    fn respond(&mut self, message: &[u8], state: &AppState) -> Response {
+        let request = match serde_json::from_slice(message) {
+            Ok(request) => request,
+            // Other vault requests have to be sent inside an encrypted session.
+            Err(_) if serde_json::from_slice::<VaultRequest>(message).is_ok() => {
+                return Response::Error(if self.authenticated {
+                    AppError::NotPaired
+                } else {
+                    AppError::Unauthorized
+                });
+            }
+            Err(e) => return Response::Error(AppError::InvalidRequest(e.to_string())),
+        };
+        let result = match request {
+            Request::Hello { version, token } => self.hello(version, &token),
+            _ if !self.authenticated => Err(AppError::Unauthorized),
+            Request::PairStart { name, public_key } => {
+                pair_start(name, &public_key, state.pairings)
+            }
+            Request::OpenSession { client_id, nonce } => {
+                self.open_session(client_id, &nonce, state.config)
+            }
+            Request::Encrypted { ciphertext } => self.encrypted(&ciphertext, state),
+            // Whether the vault is unlocked isn't a secret, and anyone may lock it.
+            Request::GetLockState => {
+                let mut vault_manager = state.vault_manager.0.lock().unwrap();
+                handle(VaultRequest::GetLockState, &mut vault_manager)
+            }
+            Request::Lock => {
+                let mut vault_manager = state.vault_manager.0.lock().unwrap();
+                handle(VaultRequest::Lock, &mut vault_manager)
+            }
+        };
+        result.unwrap_or_else(Response::Error)
+    }
+
+    fn hello(&mut self, version: u32, token: &SecretString) -> AppResult<Response> {
+        // Compared in constant time.
+        if token != self.token {
+            return Err(AppError::Unauthorized);
+        }
+        if version != PROTOCOL_VERSION {
+            return Err(AppError::UnsupportedProtocol(version));
+        }
+        self.authenticated = true;
+        Ok(Response::Hello {
+            version: PROTOCOL_VERSION,
+        })
+    }
+
+    fn open_session(
+        &mut self,
+        client_id: Uuid,
+        nonce: &str,
+        config_state: &ConfigState,
+    ) -> AppResult<Response> {
+        let nonce = decode_hex(nonce, "nonce")?;
+        let mut config = config_state.state.lock().unwrap();
+        let client = config
+            .get_paired_client_mut(client_id)
+            .ok_or(AppError::NotPaired)?;
+        let (cipher, app_nonce) = SessionCipher::open(client, &nonce)?;
+        client.last_seen = Some(unix_timestamp());
+        self.cipher = Some(cipher);
+        Ok(Response::Session {
+            nonce: hex::encode(app_nonce),
+        })
+    }
+
+    /// Decrypt and answer a vault request, the response is encrypted as well.
+    fn encrypted(&mut self, ciphertext: &str, state: &AppState) -> AppResult<Response> {
+        let client_id = self.cipher.as_ref().ok_or(AppError::NotPaired)?.client_id;
+        // Revoking a client also ends the sessions it has open.
+        if state
+            .config
+            .state
+            .lock()
+            .unwrap()
+            .get_paired_client(client_id)
+            .is_none()
+        {
+            self.cipher = None;
+            return Err(AppError::NotPaired);
+        }
+        let ciphertext = decode_hex(ciphertext, "ciphertext")?;
+        let cipher = self.cipher.as_mut().expect("the session is open");
+        let plaintext = match cipher.decrypt(&ciphertext) {
+            Ok(plaintext) => plaintext,
+            Err(e) => {
+                // The counters of both sides are out of step now, the client has to start over.
+                self.cipher = None;
+                return Err(e);
+            }
+        };
+
+        let response = match serde_json::from_slice(plaintext.unsecure()) {
+            Ok(request) => {
+                let mut vault_manager = state.vault_manager.0.lock().unwrap();
+                handle(request, &mut vault_manager)
+            }
+            Err(e) => Err(AppError::InvalidRequest(e.to_string())),
+        }
+        .unwrap_or_else(Response::Error);
+        let plaintext =
+            SecStr::new(serde_json::to_vec(&response).expect("responses always serialize"));
+        Ok(Response::Encrypted {
+            ciphertext: hex::encode(cipher.encrypt(plaintext.unsecure())?),
+        })
+    }
+}
+
+fn pair_start(name: String, public_key: &str, pairing_state: &PairingState) -> AppResult<Response> {
+    let public_key = decode_hex(public_key, "public key")?
+        .try_into()
+        .map_err(|_| AppError::InvalidRequest("The public key must be 32 bytes".into()))?;
+    let (id, public_key) = pairing_state.start(name, public_key)?;
+    Ok(Response::Pairing {
+        id,
+        public_key: hex::encode(public_key.as_bytes()),
+    })
+}
+
+fn decode_hex(value: &str, what: &str) -> AppResult<Vec<u8>> {
+    hex::decode(value).map_err(|_| AppError::InvalidRequest(format!("The {} must be hex", what)))
+}
+
+fn handle(request: VaultRequest, vault_manager: &mut VaultManager) -> AppResult<Response> {
+    match request {
+        VaultRequest::ListMatches { url } => {
+            let vault = vault_manager.get_active_vault()?;
+            let matches = vault
+                .logins_for_url(&url)?
+                .into_iter()
+                .filter_map(|entry| match &entry.data {
+                    EntryData::Login { username, url, .. } => Some(Match {
+                        id: entry.id,
+                        title: entry.title.clone(),
+                        username: username.clone(),
+                        url: url.clone(),
+                    }),
+                    _ => None,
+                })
+                .collect();
+            Ok(Response::Matches(matches))
+        }
+        VaultRequest::GetSecret { id } => {
+            let vault = vault_manager.get_active_vault()?;
+            let entry = vault.get_vault_entry(id)?;
+            let EntryData::Login {
+                username, password, ..
+            } = &entry.data
+            else {
+                return Err(AppError::InvalidRequest(format!("{} isn't a login", id)));
+            };
+            let totp = match &entry.otp {
+                Some(otp) if matches!(otp.kind, OtpKind::Totp { .. }) => {
+                    Some(otp.totp(unix_timestamp())?)
+                }
+                _ => None,
+            };
+            let secret = Secret {
+                id,
+                username: username.clone(),
+                password: password.clone(),
+                totp,
+            };
+            vault.mark_entry_used(id)?;
+            Ok(Response::Secret(secret))
+        }
+        VaultRequest::CaptureLogin {
+            url,
+            username,
+            password,
+        } => {
+            let vault = vault_manager.get_active_vault()?;
+            Ok(Response::Captured(
+                vault.capture_login(url, username, password)?,
+            ))
+        }
+        VaultRequest::GetLockState => Ok(Response::LockState(lock_state(vault_manager))),
+        VaultRequest::Lock => {
+            vault_manager.lock_active_vault()?;
+            Ok(Response::LockState(lock_state(vault_manager)))
+        }
+    }
+}
+
+fn lock_state(vault_manager: &mut VaultManager) -> LockState {
+    LockState {
+        vault: vault_manager.get_active_vault_name().map(String::from),
+        unlocked: vault_manager.get_active_vault().is_ok(),
+    }
+}
+
+#[test]
+fn test_session() {
+    use serde_json::{json, Value};
+    use x25519_dalek::{EphemeralSecret, PublicKey};
+
+    use crate::pairing::{derive_pairing, CLIENT_TO_APP, SESSION_NONCE_SIZE};
+
+    let dir = tempfile::tempdir().unwrap();
+    let vault_manager_state = VaultManagerState::new();
+    let config_state = ConfigState::new(dir.path()).unwrap();
+    let pairing_state = PairingState::new();
+    let state = AppState {
+        vault_manager: &vault_manager_state,
+        config: &config_state,
+        pairings: &pairing_state,
+    };
+    let token = SecretString::from("token");
+    let mut session = Session::new(&token);
+    let mut request = |request: Value| {
+        let message = serde_json::to_vec(&request).unwrap();
+        serde_json::to_value(session.respond(&message, &state)).unwrap()
+    };
+    let hello = |version: u32, token: &str| {
+        json!({
+            "type": "hello",
+            "version": version,
+            "token": token,
+        })
+    };
+    let get_lock_state = json!({ "type": "get_lock_state" });
+
+    assert_eq!(
+        request(get_lock_state.clone())["error"]["kind"],
+        "Unauthorized"
+    );
+    assert_eq!(request(hello(3, "wrong"))["error"]["kind"], "Unauthorized");
+    assert_eq!(
+        request(hello(2, "token"))["error"]["kind"],
+        "UnsupportedProtocol"
+    );
+    assert_eq!(request(hello(3, "token"))["hello"]["version"], 3);
+    assert_eq!(
+        request(json!({ "type": "unknown" }))["error"]["kind"],
+        "InvalidRequest"
+    );
+    assert_eq!(
+        request(get_lock_state.clone()),
+        json!({ "lock_state": { "vault": null, "unlocked": false } })
+    );
+
+    {
+        let mut vault_manager = vault_manager_state.0.lock().unwrap();
+        let vault = crate::vault::test_vault(&dir.path().join("work"), "password");
+        vault_manager.add_and_activate_vault("work", vault);
+    }
+    let list_matches = json!({ "type": "list_matches", "url": "https://example.com" });
+    assert_eq!(request(list_matches.clone())["error"]["kind"], "NotPaired");
+
+    // Pair like the extension would.
+    let client_secret = EphemeralSecret::random_from_rng(OsRng);
+    let client_public = PublicKey::from(&client_secret);
+    let pairing = request(json!({
+        "type": "pair_start",
+        "name": "Chromium",
+        "public_key": hex::encode(client_public.as_bytes()),
+    }));
+    let client_id: Uuid = serde_json::from_value(pairing["pairing"]["id"].clone()).unwrap();
+    let app_public: [u8; 32] = hex::decode(pairing["pairing"]["public_key"].as_str().unwrap())
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let app_public = PublicKey::from(app_public);
+    let (client_key, code) = derive_pairing(
+        client_secret.diffie_hellman(&app_public).as_bytes(),
+        &client_public,
+        &app_public,
+    );
+    let open_session = json!({
+        "type": "open_session",
+        "client_id": client_id,
+        "nonce": hex::encode([3; SESSION_NONCE_SIZE]),
+    });
+    assert_eq!(request(open_session.clone())["error"]["kind"], "NotPaired");
+    assert_eq!(pairing_state.requests()[0].code, code);
+    pairing_state.confirm(client_id, &config_state).unwrap();
+
+    let app_nonce = request(open_session.clone())["session"]["nonce"].clone();
+    let app_nonce = hex::decode(app_nonce.as_str().unwrap()).unwrap();
+    let mut client = SessionCipher::new(
+        client_id,
+        client_key.unsecure(),
+        &[3; SESSION_NONCE_SIZE],
+        &app_nonce,
+        CLIENT_TO_APP,
+    );
+    let seal = |client: &mut SessionCipher, request: Value| {
+        let plaintext = serde_json::to_vec(&request).unwrap();
+        json!({
+            "type": "encrypted",
+            "ciphertext": hex::encode(client.encrypt(&plaintext).unwrap()),
+        })
+    };
+    let open = |response: Value, client: &mut SessionCipher| -> Value {
+        let ciphertext = response["encrypted"]["ciphertext"].as_str().unwrap();
+        let plaintext = client.decrypt(&hex::decode(ciphertext).unwrap()).unwrap();
+        serde_json::from_slice(plaintext.unsecure()).unwrap()
+    };
+
+    let capture_login = json!({
+        "type": "capture_login",
+        "url": "https://example.com/login",
+        "username": "user",
+        "password": "hunter2",
+    });
+    let captured = open(
+        request(seal(&mut client, capture_login.clone())),
+        &mut client,
+    );
+    assert_eq!(captured["captured"]["kind"], "New");
+    // The user saves it in the app.
+    let pending = serde_json::from_value(captured["captured"]["pending"].clone()).unwrap();
+    let id = vault_manager_state
+        .0
+        .lock()
+        .unwrap()
+        .get_active_vault()
+        .unwrap()
+        .accept_pending_save(pending)
+        .unwrap();
+    let id = json!(id);
+    let captured = open(request(seal(&mut client, capture_login)), &mut client);
+    assert_eq!(
+        captured["captured"],
+        json!({ "kind": { "Duplicate": { "entry": id } }, "pending": null })
+    );
+
+    let matches = open(request(seal(&mut client, list_matches)), &mut client);
+    assert_eq!(matches["matches"][0]["id"], id);
+    assert!(matches["matches"][0].get("password").is_none());
+    let get_secret = json!({ "type": "get_secret", "id": id });
+    let secret = open(request(seal(&mut client, get_secret.clone())), &mut client);
+    assert_eq!(secret["secret"]["password"], "hunter2");
+    assert_eq!(secret["secret"]["totp"], Value::Null);
+    // Secrets are never sent in the clear, not even to a paired client.
+    assert_eq!(request(get_secret.clone())["error"]["kind"], "NotPaired");
+
+    assert_eq!(
+        request(json!({ "type": "lock" })),
+        json!({ "lock_state": { "vault": "work", "unlocked": false } })
+    );
+    let locked = open(request(seal(&mut client, get_secret.clone())), &mut client);
+    assert_eq!(locked["error"]["kind"], "VaultLocked");
+
+    // A replayed request ends the session.
+    let sealed = seal(&mut client, get_lock_state.clone());
+    open(request(sealed.clone()), &mut client);
+    assert_eq!(request(sealed)["error"]["kind"], "Encryption");
+    assert_eq!(
+        request(seal(&mut client, get_lock_state.clone()))["error"]["kind"],
+        "NotPaired"
+    );
+
+    // Revoking the client ends its open sessions.
+    request(open_session.clone());
+    config_state
+        .state
+        .lock()
+        .unwrap()
+        .remove_paired_client(client_id);
+    assert_eq!(
+        request(json!({ "type": "encrypted", "ciphertext": "00" }))["error"]["kind"],
+        "NotPaired"
+    );
+    assert_eq!(request(open_session)["error"]["kind"], "NotPaired");
+}
@@ -0,0 +1,203 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use pbkdf2::pbkdf2_hmac;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{
+    error::{AppError, AppResult, EncryptionResult},
+    secret::{empty_key, SecretKey},
+    vault::{KEY_SIZE, SALT_SIZE},
+};
+
+/// Unlock time [Kdf::calibrate] aims for when creating a new vault.
+pub const DEFAULT_UNLOCK_TIME: Duration = Duration::from_millis(750);
+
+/// Argon2id memory cost used by default and as the starting point of [Kdf::calibrate], 64 MiB.
+const ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+/// Never calibrate below the OWASP recommended minimum of 19 MiB.
+const ARGON2_MIN_MEMORY_KIB: u32 = 19 * 1024;
+const ARGON2_MIN_ITERATIONS: u32 = 2;
+const ARGON2_MAX_ITERATIONS: u32 = 64;
+const ARGON2_MAX_PARALLELISM: u32 = 4;
+
+/// Limits on the parameters of a vault being unlocked, a few times what this app ever writes.
+const PBKDF2_LIMIT_ITERATIONS: u32 = 10_000_000;
+const ARGON2_LIMIT_MEMORY_KIB: u32 = 4 * ARGON2_MEMORY_KIB;
+const ARGON2_LIMIT_ITERATIONS: u32 = 4 * ARGON2_MAX_ITERATIONS;
+const ARGON2_LIMIT_PARALLELISM: u32 = 4 * ARGON2_MAX_PARALLELISM;
+
+/// The key derivation function (and its parameters) used to turn the master password into an
+/// encryption key. This is stored in the vault header so every vault is unlocked with the
+/// parameters it was created with.
+///
+/// New variants must only ever be appended, bincode stores the variant index.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    Pbkdf2HmacSha256 {
+        iterations: u32,
+    },
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+}
+
+impl Default for Kdf {
+    fn default() -> Self {
+        Self::Argon2id {
+            memory_kib: ARGON2_MEMORY_KIB,
+            iterations: 3,
+            parallelism: ARGON2_MAX_PARALLELISM,
+        }
+    }
+}
+
+impl Kdf {
+    /// Derive a 256-bit key from `password` and `salt` using this KDF's parameters.
+    ///
+    /// The key is derived straight into a [SecretKey].
+    pub fn derive_key(
+        &self,
+        password: &[u8],
+        salt: &[u8; SALT_SIZE],
+    ) -> EncryptionResult<SecretKey> {
+        let mut key = empty_key();
+        match *self {
+            Kdf::Pbkdf2HmacSha256 { iterations } => {
+                pbkdf2_hmac::<Sha256>(password, salt, iterations, key.unsecure_mut())
+            }
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let params = Params::new(memory_kib, iterations, parallelism, Some(KEY_SIZE))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params).hash_password_into(
+                    password,
+                    salt,
+                    key.unsecure_mut(),
+                )?;
+            }
+        }
+        Ok(key)
+    }
+
+    /// Check the parameters are ones this app could have written before deriving a key with them.
+    ///
+    /// They're read from the vault header, which is only authenticated once the key is derived,
+    /// so a tampered header could otherwise make unlocking allocate terabytes or never finish.
+    pub fn check_limits(&self) -> AppResult<()> {
+        let within_limits = match *self {
+            Kdf::Pbkdf2HmacSha256 { iterations } => iterations <= PBKDF2_LIMIT_ITERATIONS,
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                memory_kib <= ARGON2_LIMIT_MEMORY_KIB
+                    && iterations <= ARGON2_LIMIT_ITERATIONS
+                    && parallelism <= ARGON2_LIMIT_PARALLELISM
+            }
+        };
+        if !within_limits {
+            return Err(AppError::InvalidVault(format!(
+                "The vault's key derivation parameters are out of range: {:?}",
+                self
+            )));
+        }
+        Ok(())
+    }
+
+    /// Pick Argon2id parameters that take roughly `target` to derive a key on this machine.
+    ///
+    /// A single derivation with one iteration is timed, if that's already slower than the target
+    /// the memory cost is halved (down to 19 MiB) until it isn't. The number of iterations is then
+    /// scaled up linearly to fill the remaining time, but never drops below 2.
+    pub fn calibrate(target: Duration) -> EncryptionResult<Self> {
+        let parallelism = thread::available_parallelism()
+            .map(|n| n.get() as u32)
+            .unwrap_or(1)
+            .min(ARGON2_MAX_PARALLELISM);
+        let salt = [0u8; SALT_SIZE];
+
+        let mut memory_kib = ARGON2_MEMORY_KIB;
+        let elapsed = loop {
+            let kdf = Kdf::Argon2id {
+                memory_kib,
+                iterations: 1,
+                parallelism,
+            };
+            let start = Instant::now();
+            kdf.derive_key(b"calibration", &salt)?;
+            let elapsed = start.elapsed();
+
+            if elapsed <= target || memory_kib / 2 < ARGON2_MIN_MEMORY_KIB {
+                break elapsed;
+            }
+            memory_kib /= 2;
+        };
+
+        let iterations = (target.as_secs_f64() / elapsed.as_secs_f64().max(f64::EPSILON)) as u32;
+        Ok(Kdf::Argon2id {
+            memory_kib,
+            iterations: iterations.clamp(ARGON2_MIN_ITERATIONS, ARGON2_MAX_ITERATIONS),
+            parallelism,
+        })
+    }
+}
+
+#[test]
+fn test_derive_key() {
+    let salt = [7u8; SALT_SIZE];
+    let argon2 = Kdf::Argon2id {
+        memory_kib: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
+    let pbkdf2 = Kdf::Pbkdf2HmacSha256 { iterations: 1_000 };
+
+    let key = argon2.derive_key(b"password", &salt).unwrap();
+    assert_eq!(key, argon2.derive_key(b"password", &salt).unwrap());
+    assert_ne!(key, argon2.derive_key(b"passw0rd", &salt).unwrap());
+    assert_ne!(key, pbkdf2.derive_key(b"password", &salt).unwrap());
+
+    // Parameters from a tampered header should error instead of panicking.
+    let invalid = Kdf::Argon2id {
+        memory_kib: 0,
+        iterations: 0,
+        parallelism: 0,
+    };
+    assert!(invalid.derive_key(b"password", &salt).is_err());
+}
+
+#[test]
+fn test_check_limits() {
+    assert!(Kdf::default().check_limits().is_ok());
+    assert!(Kdf::Pbkdf2HmacSha256 {
+        iterations: 100_000
+    }
+    .check_limits()
+    .is_ok());
+
+    // Would try to allocate 4 TiB.
+    let huge = Kdf::Argon2id {
+        memory_kib: u32::MAX,
+        iterations: 1,
+        parallelism: 1,
+    };
+    assert!(matches!(
+        huge.check_limits(),
+        Err(AppError::InvalidVault(_))
+    ));
+    assert!(Kdf::Pbkdf2HmacSha256 {
+        iterations: u32::MAX
+    }
+    .check_limits()
+    .is_err());
+}
@@ -6,12 +6,34 @@ use std::fs;
 use tauri::{App, Manager, Wry};
 
 use crate::{
+    breach::build_breach_index,
+    generator::{generate_passphrase, generate_password},
+    pairing::{confirm_pairing, get_paired_clients, get_pairing_requests, reject_pairing, revoke_paired_client, PairingState},
     state::ConfigState,
-    vault::{add_entry, create_new_vault, get_active_vault_entries, get_vaults, VaultManagerState, open_vault},
+    strength::estimate_password_strength,
+    vault::{accept_pending_save, add_entry, add_item, audit_vault, change_master_password, check_breached_passwords, close_vault, create_folder, create_new_vault, delete_entry, dismiss_pending_save, delete_folder, get_active_vault_entries, get_folder_tree, get_matching_entries, get_pending_saves, get_hotp, get_password_history, get_totp, get_vaults, list_backups, lock_vault, mark_entry_used, move_folder, rename_folder, set_entry_folder, set_entry_otp, set_entry_url_match, restore_backup, set_backup_count, set_min_master_password_score, update_entry, update_item, VaultManagerState, open_vault, open_vault_with_recovery_key, restore_password},
 };
 
+mod audit;
+mod backup;
+mod breach;
+mod capture;
+mod entry;
 mod error;
+mod folder;
+mod format;
+mod generator;
+#[cfg(unix)]
+mod ipc;
+mod kdf;
+mod otp;
+mod pairing;
+mod protocol;
+mod recovery;
+mod secret;
 mod state;
+mod strength;
+mod url_match;
 mod vault;
 
 fn main() {
@@ -21,34 +43,63 @@ fn main() {
             if let tauri::WindowEvent::CloseRequested { .. } = window_event.event() {
                 // On app shutdown save the (possibly) modified config file
                 let app_handle = window_event.window().app_handle();
-                println!("CloseRequested");
                 let config_state: tauri::State<ConfigState> = app_handle.state();
-
                 config_state.write().expect("Error writing config to file.");
 
-                let config = config_state.state.lock().unwrap();
-                let vaults = config.get_vaults();
-                println!("Vaults: {:#?}", vaults);
-
+                // Every change to a vault is written as soon as it's made, there's nothing left
+                // to save. Wipe the keys before the process exits.
                 let vault_manager_state: tauri::State<VaultManagerState> = app_handle
                     .try_state()
                     .expect("`VaultManager` should already be managed");
-                let vault_manager = vault_manager_state.0.lock().unwrap();
-                for (_, vault) in vault_manager.get_vaults() {
-                    vault.write().unwrap();
-                }
-                // for (key, path) in vaults {
-                    // let vault = vault_manager.get_vault(key).expect("Vault does not exist.");
-                    // vault.write().unwrap();
-                // }
+                vault_manager_state.0.lock().unwrap().lock_all();
             }
         })
         .invoke_handler(tauri::generate_handler![
             create_new_vault,
             add_entry,
+            update_entry,
+            delete_entry,
+            add_item,
+            update_item,
+            mark_entry_used,
+            get_password_history,
+            restore_password,
+            get_folder_tree,
+            create_folder,
+            rename_folder,
+            move_folder,
+            delete_folder,
+            set_entry_folder,
+            set_entry_otp,
+            get_totp,
+            get_hotp,
+            set_entry_url_match,
+            generate_password,
+            generate_passphrase,
+            estimate_password_strength,
+            change_master_password,
             get_active_vault_entries,
+            get_matching_entries,
+            get_pending_saves,
+            accept_pending_save,
+            dismiss_pending_save,
+            audit_vault,
+            check_breached_passwords,
+            build_breach_index,
             get_vaults,
-            open_vault
+            open_vault,
+            open_vault_with_recovery_key,
+            list_backups,
+            restore_backup,
+            set_backup_count,
+            set_min_master_password_score,
+            get_pairing_requests,
+            confirm_pairing,
+            reject_pairing,
+            get_paired_clients,
+            revoke_paired_client,
+            lock_vault,
+            close_vault
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -62,8 +113,15 @@ fn setup(app: &mut App<Wry>) -> Result<(), Box<(dyn StdError + 'static)>> {
     fs::create_dir_all(&app_dir)?;
 
     // Start managing the config state
-    app.manage(ConfigState::new(&app_dir));
+    app.manage(ConfigState::new(&app_dir)?);
     app.manage(VaultManagerState::new());
+    app.manage(PairingState::new());
+
+    // The browser integration is optional, the app works fine without it.
+    #[cfg(unix)]
+    if let Err(e) = ipc::start(app.handle()) {
+        eprintln!("Error starting the local socket: {}", e);
+    }
 
     Ok(())
 }
@@ -0,0 +1,319 @@
+//! One-time passwords stored with entries: RFC 4226 HOTP and RFC 6238 TOTP codes, imported
+//! from `otpauth://` uris or a bare base32 secret.
+
+use base32::Alphabet;
+use hmac::{Hmac, Mac};
+use percent_encoding::percent_decode_str;
+use secstr::SecStr;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use url::Url;
+
+use crate::{
+    error::{AppError, AppResult},
+    secret::SecretString,
+};
+
+const DEFAULT_DIGITS: u8 = 6;
+const DEFAULT_PERIOD: u64 = 30;
+const BASE32: Alphabet = Alphabet::RFC4648 { padding: false };
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OtpAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpKind {
+    /// Time based, a new code every `period` seconds.
+    Totp { period: u64 },
+    /// Counter based, the counter is advanced every time a code is generated.
+    Hotp { counter: u64 },
+}
+
+/// The secret and settings needed to generate the one-time passwords of an entry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OtpSecret {
+    /// Upper case base32 without padding.
+    pub secret: SecretString,
+    pub algorithm: OtpAlgorithm,
+    /// Length of the codes, 6 to 8.
+    pub digits: u8,
+    pub kind: OtpKind,
+    /// The service and account the secret belongs to, empty unless they were in the uri.
+    pub issuer: String,
+    pub account: String,
+}
+
+/// The current code of a TOTP secret.
+#[derive(Serialize, Debug, Clone)]
+pub struct TotpCode {
+    pub code: SecretString,
+    /// Until the next code.
+    pub seconds_left: u64,
+    pub period: u64,
+}
+
+impl OtpSecret {
+    /// Parse an `otpauth://totp/...` or `otpauth://hotp/...` uri, or else a base32 secret which
+    /// gets the usual TOTP settings (SHA1, 6 digits, 30 seconds).
+    pub fn parse(input: &str) -> AppResult<Self> {
+        let input = input.trim();
+        let otp = if input
+            .get(.."otpauth:".len())
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("otpauth:"))
+        {
+            Self::from_uri(input)?
+        } else {
+            Self {
+                secret: normalize_secret(input),
+                algorithm: OtpAlgorithm::Sha1,
+                digits: DEFAULT_DIGITS,
+                kind: OtpKind::Totp {
+                    period: DEFAULT_PERIOD,
+                },
+                issuer: String::new(),
+                account: String::new(),
+            }
+        };
+        otp.validate()?;
+        Ok(otp)
+    }
+
+    /// See the [key uri format](https://github.com/google/google-authenticator/wiki/Key-Uri-Format).
+    fn from_uri(uri: &str) -> AppResult<Self> {
+        let url = Url::parse(uri).map_err(|_| invalid("The otpauth uri isn't valid"))?;
+
+        let mut secret = None;
+        let mut algorithm = OtpAlgorithm::Sha1;
+        let mut digits = DEFAULT_DIGITS;
+        let mut period = DEFAULT_PERIOD;
+        let mut counter = 0;
+        let mut issuer = None;
+        for (key, value) in url.query_pairs() {
+            match key.to_ascii_lowercase().as_str() {
+                "secret" => secret = Some(normalize_secret(&value)),
+                "algorithm" => {
+                    algorithm = match value.to_ascii_uppercase().as_str() {
+                        "SHA1" => OtpAlgorithm::Sha1,
+                        "SHA256" => OtpAlgorithm::Sha256,
+                        "SHA512" => OtpAlgorithm::Sha512,
+                        _ => {
+                            return Err(invalid("The OTP algorithm must be SHA1, SHA256 or SHA512"))
+                        }
+                    }
+                }
+                "digits" => digits = parse_number(&value)?,
+                "period" => period = parse_number(&value)?,
+                "counter" => counter = parse_number(&value)?,
+                "issuer" => issuer = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+
+        let kind = match url.host_str() {
+            Some(host) if host.eq_ignore_ascii_case("totp") => OtpKind::Totp { period },
+            Some(host) if host.eq_ignore_ascii_case("hotp") => OtpKind::Hotp { counter },
+            _ => return Err(invalid("The otpauth uri must be for totp or hotp")),
+        };
+        // The label is `issuer:account` or just `account`, the issuer parameter wins.
+        let label = percent_decode_str(url.path().trim_start_matches('/')).decode_utf8_lossy();
+        let (label_issuer, account) = match label.split_once(':') {
+            Some((issuer, account)) => (issuer.trim(), account.trim()),
+            None => ("", label.trim()),
+        };
+
+        Ok(Self {
+            secret: secret.ok_or_else(|| invalid("The otpauth uri has no secret"))?,
+            algorithm,
+            digits,
+            kind,
+            issuer: issuer.unwrap_or_else(|| label_issuer.into()),
+            account: account.into(),
+        })
+    }
+
+    pub fn validate(&self) -> AppResult<()> {
+        if !(6..=8).contains(&self.digits) {
+            return Err(invalid("OTP codes must have 6 to 8 digits"));
+        }
+        if self.kind == (OtpKind::Totp { period: 0 }) {
+            return Err(invalid("The OTP period can't be 0 seconds"));
+        }
+        match self.key() {
+            Some(key) if !key.unsecure().is_empty() => Ok(()),
+            _ => Err(invalid("The OTP secret must be base32")),
+        }
+    }
+
+    fn key(&self) -> Option<SecStr> {
+        base32::decode(BASE32, self.secret.unsecure()).map(SecStr::new)
+    }
+
+    /// The HOTP code for `counter`, TOTP codes are HOTP codes of the current time step.
+    pub fn code_at(&self, counter: u64) -> AppResult<SecretString> {
+        let key = self
+            .key()
+            .ok_or_else(|| invalid("The OTP secret must be base32"))?;
+        let message = counter.to_be_bytes();
+        let hash = match self.algorithm {
+            OtpAlgorithm::Sha1 => hmac::<Hmac<Sha1>>(key.unsecure(), &message),
+            OtpAlgorithm::Sha256 => hmac::<Hmac<Sha256>>(key.unsecure(), &message),
+            OtpAlgorithm::Sha512 => hmac::<Hmac<Sha512>>(key.unsecure(), &message),
+        };
+
+        // Dynamic truncation, RFC 4226 section 5.3.
+        let offset = (hash.unsecure()[hash.unsecure().len() - 1] & 0xf) as usize;
+        let bytes: [u8; 4] = hash.unsecure()[offset..offset + 4].try_into().unwrap();
+        let truncated = u32::from_be_bytes(bytes) & 0x7fff_ffff;
+        let code = truncated % 10u32.pow(self.digits.into());
+        Ok(format!("{:0width$}", code, width = self.digits as usize).into())
+    }
+
+    /// The TOTP code at `now`, see [crate::entry::unix_timestamp].
+    pub fn totp(&self, now: u64) -> AppResult<TotpCode> {
+        let OtpKind::Totp { period } = self.kind else {
+            return Err(invalid("The entry has a counter based (HOTP) secret"));
+        };
+        Ok(TotpCode {
+            code: self.code_at(now / period)?,
+            seconds_left: period - now % period,
+            period,
+        })
+    }
+
+    /// The HOTP code of the current counter, the counter is advanced so every code is only
+    /// handed out once.
+    pub fn next_hotp(&mut self) -> AppResult<SecretString> {
+        let OtpKind::Hotp { counter } = &mut self.kind else {
+            return Err(invalid("The entry has a time based (TOTP) secret"));
+        };
+        let current = *counter;
+        *counter = current
+            .checked_add(1)
+            .ok_or_else(|| invalid("The HOTP counter can't be advanced any further"))?;
+        self.code_at(current)
+    }
+}
+
+fn hmac<M: Mac + hmac::digest::KeyInit>(key: &[u8], message: &[u8]) -> SecStr {
+    let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(message);
+    SecStr::new(mac.finalize().into_bytes().to_vec())
+}
+
+/// Secrets are often shown in groups, lower case or padded, none of which matters.
+fn normalize_secret(secret: &str) -> SecretString {
+    secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+        .map(|c| c.to_ascii_uppercase())
+        .collect::<String>()
+        .into()
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> AppResult<T> {
+    value
+        .parse()
+        .map_err(|_| invalid("The otpauth uri has an invalid number"))
+}
+
+fn invalid(message: &str) -> AppError {
+    AppError::InvalidEntry(message.into())
+}
+
+#[test]
+fn test_otp_codes() {
+    let secret = |key: &[u8], algorithm, kind| OtpSecret {
+        secret: base32::encode(BASE32, key).into(),
+        algorithm,
+        digits: 8,
+        kind,
+        issuer: String::new(),
+        account: String::new(),
+    };
+
+    // RFC 6238 appendix B.
+    let totp = OtpKind::Totp { period: 30 };
+    let sha1 = secret(b"12345678901234567890", OtpAlgorithm::Sha1, totp);
+    let sha256 = secret(
+        b"12345678901234567890123456789012",
+        OtpAlgorithm::Sha256,
+        totp,
+    );
+    let sha512 = secret(
+        b"1234567890123456789012345678901234567890123456789012345678901234",
+        OtpAlgorithm::Sha512,
+        totp,
+    );
+    for (time, codes) in [
+        (59, ["94287082", "46119246", "90693936"]),
+        (1111111109, ["07081804", "68084774", "25091201"]),
+        (20000000000, ["65353130", "77737706", "47863826"]),
+    ] {
+        for (otp, code) in [&sha1, &sha256, &sha512].into_iter().zip(codes) {
+            assert_eq!(otp.totp(time).unwrap().code.unsecure(), code);
+        }
+    }
+    assert_eq!(sha1.totp(59).unwrap().seconds_left, 1);
+
+    // RFC 4226 appendix D.
+    let mut hotp = secret(
+        b"12345678901234567890",
+        OtpAlgorithm::Sha1,
+        OtpKind::Hotp { counter: 0 },
+    );
+    hotp.digits = 6;
+    for code in ["755224", "287082", "359152"] {
+        assert_eq!(hotp.next_hotp().unwrap().unsecure(), code);
+    }
+    assert_eq!(hotp.kind, OtpKind::Hotp { counter: 3 });
+    assert!(hotp.totp(59).is_err());
+
+    // An imported counter can be anything, the last one is an error rather than an overflow.
+    hotp.kind = OtpKind::Hotp { counter: u64::MAX };
+    assert!(matches!(hotp.next_hotp(), Err(AppError::InvalidEntry(_))));
+    assert_eq!(hotp.kind, OtpKind::Hotp { counter: u64::MAX });
+}
+
+#[test]
+fn test_parse_otp() {
+    let otp = OtpSecret::parse(
+        "otpauth://totp/ACME%20Co:john@example.com?secret=HXDMVJECJJWSRB3HWIZR4IFUGFTMXBOZ\
+         &issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60",
+    )
+    .unwrap();
+    assert_eq!(otp.secret.unsecure(), "HXDMVJECJJWSRB3HWIZR4IFUGFTMXBOZ");
+    assert_eq!(otp.algorithm, OtpAlgorithm::Sha256);
+    assert_eq!(otp.digits, 8);
+    assert_eq!(otp.kind, OtpKind::Totp { period: 60 });
+    assert_eq!(otp.issuer, "ACME Co");
+    assert_eq!(otp.account, "john@example.com");
+
+    let otp =
+        OtpSecret::parse("otpauth://hotp/Example:alice?secret=JBSWY3DPEHPK3PXP&counter=5").unwrap();
+    assert_eq!(otp.kind, OtpKind::Hotp { counter: 5 });
+    assert_eq!(otp.issuer, "Example");
+
+    let otp = OtpSecret::parse("jbsw y3dp ehpk 3pxp").unwrap();
+    assert_eq!(otp.secret.unsecure(), "JBSWY3DPEHPK3PXP");
+    assert_eq!(otp.kind, OtpKind::Totp { period: 30 });
+
+    for input in [
+        "not base32!",
+        "",
+        "otpauth://totp/Example?issuer=Example",
+        "otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP&digits=10",
+        "otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP&period=0",
+        "otpauth://motp/Example?secret=JBSWY3DPEHPK3PXP",
+    ] {
+        assert!(matches!(
+            OtpSecret::parse(input),
+            Err(AppError::InvalidEntry(_))
+        ));
+    }
+}
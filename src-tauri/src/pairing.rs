@@ -0,0 +1,410 @@
+//! Pairing of local socket clients, e.g. the browser extension, and the encryption of their
+//! sessions.
+//!
+//! Pairing is an X25519 key exchange: the client sends its public key, the app answers with its
+//! own and both derive the same client key and a six digit confirmation code from the shared
+//! secret. The code is shown by both the app and the client, and only once the user confirms in
+//! the app that they match is the client key stored in the [crate::state::Config]. A man in the
+//! middle ends up with different codes.
+//!
+//! Every connection of a paired client opens a session: both sides send a random nonce and the
+//! session key is derived from the client key and both nonces. Messages are encrypted with
+//! AES-256-GCM using a per direction counter as the nonce, so they can't be replayed, reordered
+//! or reflected.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+use hkdf::Hkdf;
+use rand::{rngs::OsRng, RngCore};
+use secstr::SecStr;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tauri::Manager;
+use uuid::Uuid;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use crate::{
+    entry::unix_timestamp,
+    error::{AppError, AppResult, EncryptionError},
+    state::ConfigState,
+};
+
+const CLIENT_KEY_INFO: &[u8] = b"spartankey client key";
+const CONFIRMATION_CODE_INFO: &[u8] = b"spartankey confirmation code";
+const SESSION_KEY_INFO: &[u8] = b"spartankey session key";
+/// Pairing requests that weren't confirmed within this many seconds are dropped.
+const PAIRING_TIMEOUT: u64 = 5 * 60;
+/// Session nonces are at least this long, the app always sends this many bytes.
+pub const SESSION_NONCE_SIZE: usize = 32;
+/// First byte of the nonces of messages sent by the client.
+pub const CLIENT_TO_APP: u8 = 0;
+/// First byte of the nonces of messages sent by the app.
+pub const APP_TO_CLIENT: u8 = 1;
+
+/// A client the user confirmed, stored in the config.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PairedClient {
+    pub id: Uuid,
+    pub name: String,
+    key: SecStr,
+    /// See [unix_timestamp].
+    pub paired: u64,
+    /// Last time the client opened a session, `None` if it never did.
+    pub last_seen: Option<u64>,
+}
+
+/// A [PairedClient] as it's shown to the user, without its key.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PairedClientInfo {
+    pub id: Uuid,
+    pub name: String,
+    pub paired: u64,
+    pub last_seen: Option<u64>,
+}
+
+impl From<&PairedClient> for PairedClientInfo {
+    fn from(client: &PairedClient) -> Self {
+        Self {
+            id: client.id,
+            name: client.name.clone(),
+            paired: client.paired,
+            last_seen: client.last_seen,
+        }
+    }
+}
+
+/// A pairing waiting for the user to compare the codes, as it's shown to the user.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PairingRequest {
+    /// Also the id of the client once it's confirmed.
+    pub id: Uuid,
+    pub name: String,
+    pub code: String,
+    pub requested: u64,
+}
+
+struct PendingPairing {
+    request: PairingRequest,
+    key: SecStr,
+}
+
+/// Pairing requests waiting for the user, managed by tauri.
+pub struct PairingState(Mutex<HashMap<Uuid, PendingPairing>>);
+
+impl PairingState {
+    pub fn new() -> Self {
+        Self(Mutex::new(HashMap::new()))
+    }
+
+    /// Start pairing the client called `name` with the given X25519 `public_key`. Returns the id
+    /// of the request and the app's public key for the client.
+    pub fn start(&self, name: String, public_key: [u8; 32]) -> AppResult<(Uuid, PublicKey)> {
+        if name.trim().is_empty() {
+            return Err(AppError::InvalidRequest("The client needs a name".into()));
+        }
+        let client_public = PublicKey::from(public_key);
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let app_public = PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&client_public);
+        if !shared.was_contributory() {
+            return Err(AppError::InvalidRequest(
+                "The public key isn't a valid X25519 key".into(),
+            ));
+        }
+        let (key, code) = derive_pairing(shared.as_bytes(), &client_public, &app_public);
+
+        let id = Uuid::new_v4();
+        let request = PairingRequest {
+            id,
+            name,
+            code,
+            requested: unix_timestamp(),
+        };
+        let mut pending = self.0.lock().unwrap();
+        prune(&mut pending);
+        pending.insert(id, PendingPairing { request, key });
+        Ok((id, app_public))
+    }
+
+    /// Pairing requests that haven't timed out yet, oldest first.
+    pub fn requests(&self) -> Vec<PairingRequest> {
+        let mut pending = self.0.lock().unwrap();
+        prune(&mut pending);
+        let mut requests: Vec<PairingRequest> = pending
+            .values()
+            .map(|pairing| pairing.request.clone())
+            .collect();
+        requests.sort_by_key(|request| (request.requested, request.id));
+        requests
+    }
+
+    pub fn request(&self, id: Uuid) -> Option<PairingRequest> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|pairing| pairing.request.clone())
+    }
+
+    /// The user confirmed the codes match, store the client in the config and write it.
+    pub fn confirm(&self, id: Uuid, config_state: &ConfigState) -> AppResult<PairedClientInfo> {
+        let pairing = {
+            let mut pending = self.0.lock().unwrap();
+            prune(&mut pending);
+            pending.remove(&id).ok_or(AppError::UnknownPairing(id))?
+        };
+        let client = PairedClient {
+            id,
+            name: pairing.request.name,
+            key: pairing.key,
+            paired: unix_timestamp(),
+            last_seen: None,
+        };
+        let info = PairedClientInfo::from(&client);
+        config_state.state.lock().unwrap().add_paired_client(client);
+        config_state.write()?;
+        Ok(info)
+    }
+
+    pub fn reject(&self, id: Uuid) -> AppResult<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .map(|_| ())
+            .ok_or(AppError::UnknownPairing(id))
+    }
+}
+
+fn prune(pending: &mut HashMap<Uuid, PendingPairing>) {
+    let now = unix_timestamp();
+    pending.retain(|_, pairing| now.saturating_sub(pairing.request.requested) < PAIRING_TIMEOUT);
+}
+
+/// The client key and confirmation code both sides derive from the X25519 shared secret.
+pub fn derive_pairing(
+    shared_secret: &[u8; 32],
+    client_public: &PublicKey,
+    app_public: &PublicKey,
+) -> (SecStr, String) {
+    let salt = [client_public.as_bytes().as_slice(), app_public.as_bytes()].concat();
+    let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared_secret);
+    let mut key = SecStr::new(vec![0; 32]);
+    hkdf.expand(CLIENT_KEY_INFO, key.unsecure_mut())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    let mut code = [0u8; 4];
+    hkdf.expand(CONFIRMATION_CODE_INFO, &mut code)
+        .expect("4 bytes is a valid HKDF-SHA256 output length");
+    (key, format!("{:06}", u32::from_be_bytes(code) % 1_000_000))
+}
+
+/// The encryption of one session between the app and a paired client.
+pub struct SessionCipher {
+    pub client_id: Uuid,
+    cipher: Aes256Gcm,
+    /// [CLIENT_TO_APP] or [APP_TO_CLIENT], depending on which side this is.
+    direction: u8,
+    sent: u64,
+    received: u64,
+}
+
+impl SessionCipher {
+    /// Open a session with `client` for the app, returns the app's nonce for the client.
+    pub fn open(client: &PairedClient, client_nonce: &[u8]) -> AppResult<(Self, Vec<u8>)> {
+        if client_nonce.len() < SESSION_NONCE_SIZE {
+            return Err(AppError::InvalidRequest(format!(
+                "The session nonce must be at least {} bytes",
+                SESSION_NONCE_SIZE
+            )));
+        }
+        let mut app_nonce = vec![0; SESSION_NONCE_SIZE];
+        OsRng.fill_bytes(&mut app_nonce);
+        let cipher = Self::new(
+            client.id,
+            client.key.unsecure(),
+            client_nonce,
+            &app_nonce,
+            APP_TO_CLIENT,
+        );
+        Ok((cipher, app_nonce))
+    }
+
+    /// The cipher of either side, `direction` is the one of the messages this side sends.
+    pub fn new(
+        client_id: Uuid,
+        client_key: &[u8],
+        client_nonce: &[u8],
+        app_nonce: &[u8],
+        direction: u8,
+    ) -> Self {
+        let salt = [client_nonce, app_nonce].concat();
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), client_key);
+        let mut key = SecStr::new(vec![0; 32]);
+        hkdf.expand(SESSION_KEY_INFO, key.unsecure_mut())
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self {
+            client_id,
+            cipher: Aes256Gcm::new_from_slice(key.unsecure()).expect("the key is 32 bytes"),
+            direction,
+            sent: 0,
+            received: 0,
+        }
+    }
+
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> AppResult<Vec<u8>> {
+        let nonce = session_nonce(self.direction, self.sent);
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(EncryptionError::from)?;
+        self.sent += 1;
+        Ok(ciphertext)
+    }
+
+    /// Decrypt the next message of the other side, a message that was replayed, reordered or
+    /// tampered with fails.
+    pub fn decrypt(&mut self, ciphertext: &[u8]) -> AppResult<SecStr> {
+        let direction = if self.direction == APP_TO_CLIENT {
+            CLIENT_TO_APP
+        } else {
+            APP_TO_CLIENT
+        };
+        let nonce = session_nonce(direction, self.received);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext)
+            .map_err(EncryptionError::from)?;
+        self.received += 1;
+        Ok(SecStr::new(plaintext))
+    }
+}
+
+/// The direction byte, three zero bytes and the big endian message counter.
+fn session_nonce(direction: u8, counter: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[0] = direction;
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Returns the pairing requests waiting for the user to compare their codes, oldest first
+pub fn get_pairing_requests(app_handle: tauri::AppHandle<tauri::Wry>) -> Vec<PairingRequest> {
+    let pairing_state: tauri::State<PairingState> = app_handle.state();
+    pairing_state.requests()
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Pairs the client of request `id` after the user confirmed its code matches the one shown by
+/// the client
+pub fn confirm_pairing(
+    id: Uuid,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<PairedClientInfo> {
+    let pairing_state: tauri::State<PairingState> = app_handle.state();
+    let config_state: tauri::State<ConfigState> = app_handle.state();
+    pairing_state.confirm(id, &config_state)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Drops the pairing request `id`, e.g. because its code didn't match
+pub fn reject_pairing(id: Uuid, app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<()> {
+    let pairing_state: tauri::State<PairingState> = app_handle.state();
+    pairing_state.reject(id)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Returns the paired clients
+pub fn get_paired_clients(app_handle: tauri::AppHandle<tauri::Wry>) -> Vec<PairedClientInfo> {
+    let config_state: tauri::State<ConfigState> = app_handle.state();
+    let config = config_state.state.lock().unwrap();
+    config
+        .get_paired_clients()
+        .iter()
+        .map(PairedClientInfo::from)
+        .collect()
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Revokes the paired client `id`, its open sessions stop working immediately
+pub fn revoke_paired_client(id: Uuid, app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<()> {
+    let config_state: tauri::State<ConfigState> = app_handle.state();
+    if !config_state.state.lock().unwrap().remove_paired_client(id) {
+        return Err(AppError::UnknownPairing(id));
+    }
+    Ok(config_state.write()?)
+}
+
+#[test]
+fn test_pairing() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_state = ConfigState::new(dir.path()).unwrap();
+    let pairing_state = PairingState::new();
+
+    let client_secret = EphemeralSecret::random_from_rng(OsRng);
+    let client_public = PublicKey::from(&client_secret);
+    let (id, app_public) = pairing_state
+        .start("Chromium".into(), client_public.to_bytes())
+        .unwrap();
+    assert!(pairing_state.start("Chromium".into(), [0; 32]).is_err());
+
+    // The client derives the same code on its side.
+    let shared = client_secret.diffie_hellman(&app_public);
+    let (client_key, code) = derive_pairing(shared.as_bytes(), &client_public, &app_public);
+    let requests = pairing_state.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].code, code);
+    assert_eq!(code.len(), 6);
+
+    pairing_state.confirm(id, &config_state).unwrap();
+    assert!(pairing_state.requests().is_empty());
+    assert!(matches!(
+        pairing_state.confirm(id, &config_state),
+        Err(AppError::UnknownPairing(_))
+    ));
+    // The pairing survives a restart.
+    let config_state = ConfigState::new(dir.path()).unwrap();
+    let paired = config_state
+        .state
+        .lock()
+        .unwrap()
+        .get_paired_client(id)
+        .cloned()
+        .unwrap();
+    assert_eq!(paired.name, "Chromium");
+    assert!(SessionCipher::open(&paired, &[7; 8]).is_err());
+
+    let client_nonce = [7; SESSION_NONCE_SIZE];
+    let (mut app, app_nonce) = SessionCipher::open(&paired, &client_nonce).unwrap();
+    let mut client = SessionCipher::new(
+        id,
+        client_key.unsecure(),
+        &client_nonce,
+        &app_nonce,
+        CLIENT_TO_APP,
+    );
+    let request = client.encrypt(b"request").unwrap();
+    assert_eq!(app.decrypt(&request).unwrap().unsecure(), b"request");
+    // A replayed message doesn't decrypt, neither does one reflected back at its sender.
+    assert!(app.decrypt(&request).is_err());
+    let response = app.encrypt(b"response").unwrap();
+    assert!(app.decrypt(&response).is_err());
+    assert_eq!(client.decrypt(&response).unwrap().unsecure(), b"response");
+
+    // The config holds the client keys, only the user may read it.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata = std::fs::metadata(dir.path().join("config")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    }
+    // A corrupt config is an error rather than a crash.
+    std::fs::write(dir.path().join("config"), b"SPKC\x03\x00").unwrap();
+    assert!(ConfigState::new(dir.path()).is_err());
+}
@@ -0,0 +1,96 @@
+//! Message framing shared by the app's local socket and the native messaging host.
+//!
+//! Every message is a JSON document prefixed with its length as a native endian `u32`, exactly
+//! like Chrome's native messaging, so the host can pass messages between the browser and the
+//! app without looking at them. This file is also compiled into the host binary, so it can only
+//! use `std`.
+
+use std::{
+    env,
+    io::{self, Read, Write},
+    path::PathBuf,
+};
+
+/// Version of the requests and responses sent over the socket, clients name the version they
+/// speak when they connect.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Chrome doesn't accept messages from a host that are larger than 1 MB, requests are held to
+/// the same limit.
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// The directory holding the socket and its token, `$XDG_RUNTIME_DIR/spartankey` or a per-user
+/// directory in the temporary directory if there's no runtime directory. The app only binds the
+/// socket once nobody but the current user can get into it.
+pub fn socket_dir() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("spartankey"),
+        _ => {
+            let user = env::var("USER").unwrap_or_default();
+            env::temp_dir().join(format!("spartankey-{}", user))
+        }
+    }
+}
+
+/// Where the running app listens.
+pub fn socket_path() -> PathBuf {
+    socket_dir().join("spartankey.sock")
+}
+
+/// The file holding the token clients need to use the socket, next to the socket.
+pub fn token_path() -> PathBuf {
+    socket_dir().join("spartankey.token")
+}
+
+/// Read the next message, `None` if the other side closed the connection between messages.
+pub fn read_message(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0u8; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let length = u32::from_ne_bytes(length) as usize;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Message of {} bytes is too large", length),
+        ));
+    }
+    let mut message = vec![0; length];
+    reader.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &[u8]) -> io::Result<()> {
+    if message.len() > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Message of {} bytes is too large", message.len()),
+        ));
+    }
+    writer.write_all(&(message.len() as u32).to_ne_bytes())?;
+    writer.write_all(message)?;
+    writer.flush()
+}
+
+#[test]
+fn test_message_framing() {
+    let mut buffer = Vec::new();
+    write_message(&mut buffer, br#"{"type":"get_logins"}"#).unwrap();
+    write_message(&mut buffer, b"{}").unwrap();
+    assert_eq!(&buffer[..4], &21u32.to_ne_bytes());
+
+    let mut reader = buffer.as_slice();
+    assert_eq!(
+        read_message(&mut reader).unwrap().unwrap(),
+        br#"{"type":"get_logins"}"#
+    );
+    assert_eq!(read_message(&mut reader).unwrap().unwrap(), b"{}");
+    assert_eq!(read_message(&mut reader).unwrap(), None);
+
+    let too_large = ((MAX_MESSAGE_SIZE + 1) as u32).to_ne_bytes();
+    assert!(read_message(&mut too_large.as_slice()).is_err());
+    // A message cut off halfway is an error, not the end of the stream.
+    assert!(read_message(&mut &buffer[..10]).is_err());
+}
@@ -0,0 +1,56 @@
+use base32::Alphabet;
+use secstr::SecStr;
+
+use crate::secret::{key_from_slice, SecretKey, SecretString};
+
+/// Number of characters between each `-` in an encoded recovery key.
+const GROUP_SIZE: usize = 4;
+
+/// Encode a recovery key so it can be written down by hand.
+///
+/// Uses Crockford's base32 (no `I`, `L`, `O` or `U`) split into groups of four characters,
+/// e.g. `8X2M-0QZ4-...`, 13 groups for a 256-bit key.
+pub fn encode_recovery_key(key: &SecretKey) -> SecretString {
+    let encoded = SecretString::from(base32::encode(Alphabet::Crockford, key.unsecure()));
+    encoded
+        .unsecure()
+        .as_bytes()
+        .chunks(GROUP_SIZE)
+        .map(|group| std::str::from_utf8(group).unwrap())
+        .collect::<Vec<_>>()
+        .join("-")
+        .into()
+}
+
+/// Decode a recovery key typed in by the user.
+///
+/// Dashes and whitespace are ignored, the key is case insensitive and the commonly confused
+/// `I`/`L` and `O` are read as `1` and `0`.
+///
+/// Returns `None` if the input isn't a valid 256-bit recovery key.
+pub fn decode_recovery_key(encoded: &str) -> Option<SecretKey> {
+    let cleaned = SecretString::from(
+        encoded
+            .chars()
+            .filter(|c| *c != '-' && !c.is_whitespace())
+            .collect::<String>(),
+    );
+    let decoded = SecStr::new(base32::decode(Alphabet::Crockford, cleaned.unsecure())?);
+    key_from_slice(decoded.unsecure())
+}
+
+#[test]
+fn test_recovery_key_roundtrip() {
+    let key = crate::secret::random_key();
+    let encoded = encode_recovery_key(&key);
+    let encoded = encoded.unsecure();
+    assert_eq!(encoded.split('-').count(), 13);
+    assert_eq!(decode_recovery_key(encoded), Some(key.clone()));
+
+    // Sloppy transcriptions should still decode to the same key.
+    let sloppy = encoded.replace('-', " ").to_lowercase().replace('0', "o");
+    assert_eq!(decode_recovery_key(&sloppy), Some(key.clone()));
+
+    assert_eq!(decode_recovery_key("not-a-key"), None);
+    assert_eq!(decode_recovery_key(&encoded[..encoded.len() - 5]), None);
+}
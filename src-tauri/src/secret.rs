@@ -0,0 +1,128 @@
+//! Types for holding keys and passwords in memory.
+//!
+//! Both are backed by [secstr], so their contents are locked into memory (never swapped to disk),
+//! compared in constant time, printed as `***SECRET***` and zeroed when they're dropped. Plain
+//! `String`s, `Vec`s or arrays must never hold key material or a password for longer than it takes
+//! to move them into one of these.
+
+use std::fmt;
+
+use rand::{rngs::OsRng, RngCore};
+use secstr::{SecBox, SecUtf8};
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::vault::KEY_SIZE;
+
+/// A 256-bit key (the internal key, a key derived from the master password or the recovery key).
+pub type SecretKey = SecBox<[u8; KEY_SIZE]>;
+
+/// An all zero key that's meant to be filled in place, so the key bytes never exist outside of
+/// the secure allocation.
+pub fn empty_key() -> SecretKey {
+    SecBox::new(Box::new([0; KEY_SIZE]))
+}
+
+/// A new key from the OS' cryptographically secure RNG.
+pub fn random_key() -> SecretKey {
+    let mut key = empty_key();
+    OsRng.fill_bytes(key.unsecure_mut());
+    key
+}
+
+/// Copy `bytes` into a new key, `None` if it isn't exactly [KEY_SIZE] bytes long.
+pub fn key_from_slice(bytes: &[u8]) -> Option<SecretKey> {
+    if bytes.len() != KEY_SIZE {
+        return None;
+    }
+    let mut key = empty_key();
+    key.unsecure_mut().copy_from_slice(bytes);
+    Some(key)
+}
+
+/// A password (or any other secret text) that's zeroed when dropped.
+///
+/// Serializes exactly like a `String`, so it can replace one in the vault entries without
+/// changing the file format. Only the `SecretString` itself is zeroed: whatever it was
+/// deserialized from, e.g. the IPC message and JSON value a tauri command argument is read from,
+/// is left as it was.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretString(SecUtf8);
+
+impl SecretString {
+    /// Borrow the secret. Don't keep copies of it around.
+    pub fn unsecure(&self) -> &str {
+        self.0.unsecure()
+    }
+}
+
+impl Default for SecretString {
+    fn default() -> Self {
+        String::new().into()
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        Self(SecUtf8::from(value))
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        value.to_string().into()
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***SECRET***")
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.unsecure())
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SecretStringVisitor;
+
+        impl<'de> Visitor<'de> for SecretStringVisitor {
+            type Value = SecretString;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a string")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(v.into())
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+                Ok(v.into())
+            }
+        }
+
+        deserializer.deserialize_string(SecretStringVisitor)
+    }
+}
+
+#[test]
+fn test_secret_string_serializes_like_string() {
+    let secret = SecretString::from("hunter2");
+    let bytes = bincode::serialize(&secret).unwrap();
+    assert_eq!(bytes, bincode::serialize("hunter2").unwrap());
+    assert_eq!(
+        bincode::deserialize::<SecretString>(&bytes).unwrap(),
+        secret
+    );
+    assert_eq!(format!("{:?}", secret), "***SECRET***");
+
+    let json: SecretString = serde_json::from_str("\"hunter2\"").unwrap();
+    assert_eq!(json.unsecure(), "hunter2");
+}
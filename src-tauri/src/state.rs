@@ -7,11 +7,93 @@ use std::{
 };
 
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+use crate::{backup, error::AppResult, pairing::PairedClient};
+
+/// Bytes every versioned config file starts with. Unversioned configs start with the number of
+/// vaults as a `u64` instead.
+const CONFIG_MAGIC: [u8; 4] = *b"SPKC";
+const CONFIG_VERSION: u16 = 3;
+
+/// How many backups of each vault are kept unless configured otherwise.
+const DEFAULT_BACKUP_COUNT: usize = 5;
+/// The strength score new master passwords need unless configured otherwise, 3 is what zxcvbn
+/// considers safe against an offline attack on a slow hash.
+const DEFAULT_MIN_MASTER_PASSWORD_SCORE: u8 = 3;
+
+#[derive(Serialize, Deserialize, Debug)]
 /// Structure for managing the mapping between vault names and where they're stored on disk.
 pub struct Config {
-    vaults: HashMap<String, PathBuf>
+    vaults: HashMap<String, PathBuf>,
+    /// Number of previous versions of each vault kept next to it as `<vault>.bak.N`.
+    backup_count: usize,
+    /// Strength score from 0 to 4 every new master password needs, including changed ones.
+    min_master_password_score: u8,
+    /// Local socket clients the user paired, see [crate::pairing].
+    paired_clients: Vec<PairedClient>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            vaults: HashMap::default(),
+            backup_count: DEFAULT_BACKUP_COUNT,
+            min_master_password_score: DEFAULT_MIN_MASTER_PASSWORD_SCORE,
+            paired_clients: Vec::new(),
+        }
+    }
+}
+
+/// Config written before the config file was versioned.
+#[derive(Serialize, Deserialize, Debug)]
+struct ConfigV0 {
+    vaults: HashMap<String, PathBuf>,
+}
+
+impl From<ConfigV0> for Config {
+    fn from(value: ConfigV0) -> Self {
+        Self {
+            vaults: value.vaults,
+            ..Default::default()
+        }
+    }
+}
+
+/// Config version 1, without the minimum master password score.
+#[derive(Serialize, Deserialize, Debug)]
+struct ConfigV1 {
+    vaults: HashMap<String, PathBuf>,
+    backup_count: usize,
+}
+
+impl From<ConfigV1> for Config {
+    fn from(value: ConfigV1) -> Self {
+        Self {
+            vaults: value.vaults,
+            backup_count: value.backup_count,
+            ..Default::default()
+        }
+    }
+}
+
+/// Config version 2, without paired clients.
+#[derive(Serialize, Deserialize, Debug)]
+struct ConfigV2 {
+    vaults: HashMap<String, PathBuf>,
+    backup_count: usize,
+    min_master_password_score: u8,
+}
+
+impl From<ConfigV2> for Config {
+    fn from(value: ConfigV2) -> Self {
+        Self {
+            vaults: value.vaults,
+            backup_count: value.backup_count,
+            min_master_password_score: value.min_master_password_score,
+            ..Default::default()
+        }
+    }
 }
 
 impl Config {
@@ -20,22 +102,78 @@ impl Config {
         self.vaults.insert(vault_name.into(), vault_path.into());
     }
 
-    /// Serialize the config to bytes using bincode
+    /// Serialize the config to bytes using bincode, prefixed by the magic and config version.
     fn serialize(&self) -> Vec<u8> {
-        bincode::serialize(&self).unwrap()
+        let mut bytes = Vec::from(CONFIG_MAGIC);
+        bytes.extend(CONFIG_VERSION.to_le_bytes());
+        bytes.extend(bincode::serialize(&self).unwrap());
+        bytes
     }
 
-    pub fn get_vault_names(&self) -> Vec<String> {
-        self.vaults.keys().map(|key| key.clone()).collect()
+    /// Deserialize a config written by any version, upgrading it to the current [Config].
+    fn deserialize(bytes: &[u8]) -> bincode::Result<Self> {
+        match bytes.strip_prefix(&CONFIG_MAGIC) {
+            Some(rest) if rest.len() >= 2 => match u16::from_le_bytes([rest[0], rest[1]]) {
+                1 => Ok(bincode::deserialize::<ConfigV1>(&rest[2..])?.into()),
+                2 => Ok(bincode::deserialize::<ConfigV2>(&rest[2..])?.into()),
+                3 => bincode::deserialize(&rest[2..]),
+                version => Err(Box::new(bincode::ErrorKind::Custom(format!(
+                    "Unsupported config version {}",
+                    version
+                )))),
+            },
+            _ => Ok(bincode::deserialize::<ConfigV0>(bytes)?.into()),
+        }
     }
 
-    pub fn get_vaults(&self) -> &HashMap<String, PathBuf> {
-        &self.vaults
+    pub fn get_vault_names(&self) -> Vec<String> {
+        self.vaults.keys().map(|key| key.clone()).collect()
     }
 
     pub fn get_path(&self, name: &str) -> Option<&PathBuf> {
         self.vaults.get(name)
     }
+
+    pub fn get_backup_count(&self) -> usize {
+        self.backup_count
+    }
+
+    pub fn set_backup_count(&mut self, backup_count: usize) {
+        self.backup_count = backup_count;
+    }
+
+    pub fn get_min_master_password_score(&self) -> u8 {
+        self.min_master_password_score
+    }
+
+    pub fn set_min_master_password_score(&mut self, score: u8) {
+        self.min_master_password_score = score;
+    }
+
+    pub fn get_paired_clients(&self) -> &[PairedClient] {
+        &self.paired_clients
+    }
+
+    pub fn get_paired_client(&self, id: Uuid) -> Option<&PairedClient> {
+        self.paired_clients.iter().find(|client| client.id == id)
+    }
+
+    pub fn get_paired_client_mut(&mut self, id: Uuid) -> Option<&mut PairedClient> {
+        self.paired_clients
+            .iter_mut()
+            .find(|client| client.id == id)
+    }
+
+    pub fn add_paired_client(&mut self, client: PairedClient) {
+        self.paired_clients.push(client);
+    }
+
+    /// Returns whether there was a client with this id.
+    pub fn remove_paired_client(&mut self, id: Uuid) -> bool {
+        let count = self.paired_clients.len();
+        self.paired_clients.retain(|client| client.id != id);
+        self.paired_clients.len() != count
+    }
 }
 
 /// Wrapper for [Config] allowing modification from seperate threads(or tauri commands) with a mutex
@@ -45,34 +183,35 @@ pub struct ConfigState {
 }
 
 impl ConfigState {
-    /// Create a new config state, if there already exists a `config` binary file in the app_dir then 
+    /// Create a new config state, if there already exists a `config` binary file in the app_dir then
     /// that will be loaded.
-    /// 
-    /// If there is no config file, and empty one is created.
-    pub fn new(app_dir: &Path) -> Self {
+    ///
+    /// If there is no config file, and empty one is created. Returns an error if the config file
+    /// can't be read or is formatted incorrectly.
+    pub fn new(app_dir: &Path) -> AppResult<Self> {
         let path = app_dir.join("config");
         // If the path already exists, read the config file otherwise create empty one.
         let config = if path.exists() {
-            let bytes = fs::read(&path).unwrap();
-            bincode::deserialize(&bytes)
-                .expect("Could not deserialize config file, is it formatted incorrectly?")
+            let bytes = fs::read(&path)?;
+            Config::deserialize(&bytes)?
         } else {
             Config::default()
         };
 
-        println!("Loaded config: {:#?}", config);
-
-        Self {
+        Ok(Self {
             path,
             state: Mutex::new(config),
-        }
+        })
     }
 
-    /// Write the config state to the `config` file
+    /// Write the config state to the `config` file.
+    ///
+    /// The config holds the keys of paired clients, so it's only readable by the user and
+    /// replaced the same way a vault is, a crash never leaves a truncated config behind. The config
+    /// stays locked until it's written so two writes never share the temporary file.
     pub fn write(&self) -> io::Result<()> {
-        let bytes = &self.state.lock().unwrap().serialize();
-        fs::write(&self.path, bytes)?;
-        Ok(())
+        let config = self.state.lock().unwrap();
+        backup::write_without_backup(&self.path, &config.serialize())
     }
 
     /// Add a vault to the `state`
@@ -80,5 +219,47 @@ impl ConfigState {
         let config = &mut self.state.lock().unwrap();
         config.add_vault(vault_name, vault_path);
     }
+}
+
+#[test]
+fn test_upgrade_legacy_config() {
+    let legacy = ConfigV0 {
+        vaults: HashMap::from([("work".into(), PathBuf::from("/tmp/work"))]),
+    };
+    let config = Config::deserialize(&bincode::serialize(&legacy).unwrap()).unwrap();
+    assert_eq!(config.get_path("work"), Some(&PathBuf::from("/tmp/work")));
+    assert_eq!(config.get_backup_count(), DEFAULT_BACKUP_COUNT);
 
-}
\ No newline at end of file
+    let v1 = ConfigV1 {
+        vaults: HashMap::new(),
+        backup_count: 7,
+    };
+    let mut bytes = Vec::from(CONFIG_MAGIC);
+    bytes.extend(1u16.to_le_bytes());
+    bytes.extend(bincode::serialize(&v1).unwrap());
+    let config = Config::deserialize(&bytes).unwrap();
+    assert_eq!(config.get_backup_count(), 7);
+    assert_eq!(
+        config.get_min_master_password_score(),
+        DEFAULT_MIN_MASTER_PASSWORD_SCORE
+    );
+
+    let v2 = ConfigV2 {
+        vaults: HashMap::new(),
+        backup_count: 3,
+        min_master_password_score: 2,
+    };
+    let mut bytes = Vec::from(CONFIG_MAGIC);
+    bytes.extend(2u16.to_le_bytes());
+    bytes.extend(bincode::serialize(&v2).unwrap());
+    let config = Config::deserialize(&bytes).unwrap();
+    assert_eq!(config.get_min_master_password_score(), 2);
+    assert!(config.get_paired_clients().is_empty());
+
+    let mut config = Config::default();
+    config.set_backup_count(2);
+    config.set_min_master_password_score(4);
+    let read = Config::deserialize(&config.serialize()).unwrap();
+    assert_eq!(read.get_backup_count(), 2);
+    assert_eq!(read.get_min_master_password_score(), 4);
+}
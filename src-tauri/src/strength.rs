@@ -0,0 +1,994 @@
+//! Password strength estimation in the style of zxcvbn.
+//!
+//! The password is split into the sequence of patterns (common passwords and words, keyboard
+//! walks, repeats, sequences, dates and brute forced characters) that an attacker would need the
+//! fewest guesses to find, and the guesses for that sequence are the estimate.
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use serde::Serialize;
+
+use crate::{entry::unix_timestamp, generator::EFF_WORD_LIST, secret::SecretString};
+
+const COMMON_PASSWORDS: &str = include_str!("../resources/common_passwords.txt");
+
+/// Only this many characters are analyzed, longer passwords are estimated by their start.
+const MAX_ANALYZED_LENGTH: usize = 100;
+/// Guesses per brute forced character.
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+/// Every pattern added to a sequence costs at least this many guesses, otherwise lots of tiny
+/// patterns would beat brute force.
+const MIN_GUESSES_BEFORE_GROWING_SEQUENCE: f64 = 1e4;
+/// Guesses per second of an offline attack against a slow hash like the vault's KDF.
+const GUESSES_PER_SECOND: f64 = 1e4;
+/// Years closer to the current one than this are all assumed to be equally likely.
+const MIN_YEAR_SPACE: f64 = 20.0;
+const DATE_SEPARATORS: &str = " /\\_.-";
+
+/// Characters commonly substituted for letters.
+const L33T_TABLE: &[(char, &[char])] = &[
+    ('4', &['a']),
+    ('@', &['a']),
+    ('8', &['b']),
+    ('(', &['c']),
+    ('{', &['c']),
+    ('[', &['c']),
+    ('<', &['c']),
+    ('3', &['e']),
+    ('6', &['g']),
+    ('9', &['g']),
+    ('1', &['i', 'l']),
+    ('!', &['i']),
+    ('|', &['i', 'l']),
+    ('7', &['l', 't']),
+    ('0', &['o']),
+    ('$', &['s']),
+    ('5', &['s']),
+    ('+', &['t']),
+    ('%', &['x']),
+    ('2', &['z']),
+];
+/// Stops a password full of ambiguous substitutions from taking forever.
+const MAX_L33T_CANDIDATES: usize = 16;
+
+/// The QWERTY layout as `(row offset, unshifted keys, shifted keys)`. Rows are offset so the keys
+/// touching `(x, y)` are at `(x, y - 1)` and `(x + 1, y - 1)` above and `(x - 1, y + 1)` and
+/// `(x, y + 1)` below.
+const QWERTY: [(i32, &str, &str); 4] = [
+    (0, "`1234567890-=", "~!@#$%^&*()_+"),
+    (1, "qwertyuiop[]\\", "QWERTYUIOP{}|"),
+    (1, "asdfghjkl;'", "ASDFGHJKL:\""),
+    (1, "zxcvbnm,./", "ZXCVBNM<>?"),
+];
+/// Offsets of the neighbours of a key, in the order the walk directions are numbered.
+const NEIGHBOURS: [(i32, i32); 6] = [(-1, 0), (0, -1), (1, -1), (1, 0), (0, 1), (-1, 1)];
+
+/// How hard a password is to guess.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Strength {
+    /// From 0 (too guessable) to 4 (very unguessable), the same scale zxcvbn uses.
+    pub score: u8,
+    /// The estimated number of guesses needed to find the password, as a power of ten.
+    pub guesses_log10: f64,
+    /// Time to find the password with an offline attack against a slow hash.
+    pub crack_time_seconds: f64,
+    /// [Strength::crack_time_seconds] in words, like "3 hours" or "centuries".
+    pub crack_time_display: String,
+    pub feedback: Feedback,
+}
+
+/// What's wrong with a password and how to improve it. Empty for passwords scoring 3 or more.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Feedback {
+    pub warning: Option<String>,
+    pub suggestions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dictionary {
+    CommonPasswords,
+    EnglishWords,
+    UserInputs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pattern {
+    Dictionary {
+        dictionary: Dictionary,
+        rank: usize,
+        reversed: bool,
+        l33t: bool,
+    },
+    Spatial {
+        turns: usize,
+    },
+    Repeat {
+        block_len: usize,
+    },
+    Sequence,
+    Year,
+    Date,
+    BruteForce,
+}
+
+/// A pattern found at `start..end` of the password (in chars).
+#[derive(Debug, Clone, Copy)]
+struct Match {
+    start: usize,
+    end: usize,
+    guesses: f64,
+    pattern: Pattern,
+}
+
+/// One way to cover the start of the password with `len` matches, the last of which is `last`.
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    product: f64,
+    guesses: f64,
+    last: Option<Match>,
+}
+
+/// Estimate how hard `password` is to guess. `user_inputs` are things the password shouldn't be
+/// based on, like the vault's name or an entry's username and url.
+pub fn estimate(password: &str, user_inputs: &[&str]) -> Strength {
+    let mut chars: Vec<char> = password.chars().take(MAX_ANALYZED_LENGTH).collect();
+    let user_dictionary = user_dictionary(user_inputs);
+    let (guesses, sequence) = most_guessable_sequence(&chars, &user_dictionary);
+    let score = score(guesses);
+    let crack_time_seconds = guesses / GUESSES_PER_SECOND;
+    let feedback = feedback(score, &sequence, &chars);
+    chars.fill('\0');
+
+    Strength {
+        score,
+        guesses_log10: guesses.log10(),
+        crack_time_seconds,
+        crack_time_display: display_time(crack_time_seconds),
+        feedback,
+    }
+}
+
+/// The fewest guesses needed to find `chars` and the matches they come from, found by dynamic
+/// programming over every prefix of the password.
+fn most_guessable_sequence(
+    chars: &[char],
+    user_dictionary: &HashMap<String, usize>,
+) -> (f64, Vec<Match>) {
+    let n = chars.len();
+    let matches = find_matches(chars, user_dictionary);
+
+    // `best[end][len]` is the best way to cover `chars[..end]` with `len` matches.
+    let mut best: Vec<HashMap<usize, Step>> = vec![HashMap::new(); n + 1];
+    best[0].insert(
+        0,
+        Step {
+            product: 1.0,
+            guesses: 1.0,
+            last: None,
+        },
+    );
+    for end in 1..=n {
+        for m in matches.iter().filter(|m| m.end == end) {
+            let mut m = *m;
+            // Patterns that don't cover the whole password are never cheaper than this.
+            if m.end - m.start < n {
+                let min_guesses = if m.end - m.start == 1 { 10.0 } else { 50.0 };
+                m.guesses = m.guesses.max(min_guesses);
+            }
+            extend(&mut best, m);
+        }
+        for start in 0..end {
+            let len = end - start;
+            let min_guesses = if len == 1 { 11.0 } else { 51.0 };
+            extend(
+                &mut best,
+                Match {
+                    start,
+                    end,
+                    guesses: BRUTEFORCE_CARDINALITY
+                        .powi(len as i32)
+                        .clamp(min_guesses, f64::MAX),
+                    pattern: Pattern::BruteForce,
+                },
+            );
+        }
+    }
+
+    let (mut len, step) = best[n]
+        .iter()
+        .min_by(|(_, a), (_, b)| a.guesses.total_cmp(&b.guesses))
+        .map(|(len, step)| (*len, *step))
+        .unwrap();
+    let mut sequence = Vec::with_capacity(len);
+    let mut end = n;
+    while len > 0 {
+        let m = best[end][&len].last.unwrap();
+        sequence.push(m);
+        end = m.start;
+        len -= 1;
+    }
+    sequence.reverse();
+    (step.guesses, sequence)
+}
+
+/// Append `m` to every way of covering the password up to its start.
+fn extend(best: &mut [HashMap<usize, Step>], m: Match) {
+    let previous: Vec<(usize, Step)> = best[m.start]
+        .iter()
+        .map(|(len, step)| (*len, *step))
+        .collect();
+    for (len, step) in previous {
+        // Two brute forced matches in a row are always worse than one covering both.
+        let after_bruteforce = matches!(
+            step.last,
+            Some(Match {
+                pattern: Pattern::BruteForce,
+                ..
+            })
+        );
+        if after_bruteforce && m.pattern == Pattern::BruteForce {
+            continue;
+        }
+
+        let len = len + 1;
+        let product = step.product * m.guesses;
+        let guesses =
+            factorial(len) * product + MIN_GUESSES_BEFORE_GROWING_SEQUENCE.powi(len as i32 - 1);
+        // Not worth keeping if a sequence of as many or fewer matches is already cheaper.
+        let dominated = best[m.end]
+            .iter()
+            .any(|(other_len, other)| *other_len <= len && other.guesses <= guesses);
+        if !dominated {
+            best[m.end].insert(
+                len,
+                Step {
+                    product,
+                    guesses,
+                    last: Some(m),
+                },
+            );
+        }
+    }
+}
+
+fn find_matches(chars: &[char], user_dictionary: &HashMap<String, usize>) -> Vec<Match> {
+    let mut matches = dictionary_matches(chars, user_dictionary);
+    matches.extend(reversed_dictionary_matches(chars, user_dictionary));
+    matches.extend(l33t_matches(chars, user_dictionary));
+    matches.extend(spatial_matches(chars));
+    matches.extend(repeat_matches(chars, user_dictionary));
+    matches.extend(sequence_matches(chars));
+    matches.extend(date_matches(chars));
+    matches
+}
+
+/// The bundled dictionaries, lowercase words mapped to their rank (1 for the most common).
+fn dictionaries() -> &'static [(Dictionary, HashMap<String, usize>)] {
+    static DICTIONARIES: OnceLock<Vec<(Dictionary, HashMap<String, usize>)>> = OnceLock::new();
+    DICTIONARIES.get_or_init(|| {
+        let mut common_passwords = HashMap::new();
+        for (i, password) in COMMON_PASSWORDS.lines().enumerate() {
+            common_passwords
+                .entry(password.to_lowercase())
+                .or_insert(i + 1);
+        }
+        // The word list isn't sorted by frequency, so every word is as likely as any other.
+        let words: Vec<&str> = EFF_WORD_LIST
+            .lines()
+            .filter_map(|line| line.split_whitespace().last())
+            .collect();
+        let english_words = words
+            .iter()
+            .map(|word| (word.to_lowercase(), words.len()))
+            .collect();
+        vec![
+            (Dictionary::CommonPasswords, common_passwords),
+            (Dictionary::EnglishWords, english_words),
+        ]
+    })
+}
+
+/// `user_inputs` and the words in them as a dictionary, in the order they were given.
+fn user_dictionary(user_inputs: &[&str]) -> HashMap<String, usize> {
+    let mut dictionary = HashMap::new();
+    for input in user_inputs {
+        let input = input.to_lowercase();
+        let words = input
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| word.chars().count() >= 3);
+        for word in std::iter::once(input.as_str()).chain(words) {
+            if !word.is_empty() {
+                let rank = dictionary.len() + 1;
+                dictionary.entry(word.to_string()).or_insert(rank);
+            }
+        }
+    }
+    dictionary
+}
+
+/// The lowest rank of `word` in any dictionary.
+fn lookup(word: &str, user_dictionary: &HashMap<String, usize>) -> Option<(Dictionary, usize)> {
+    dictionaries()
+        .iter()
+        .map(|(dictionary, words)| (*dictionary, words))
+        .chain(std::iter::once((Dictionary::UserInputs, user_dictionary)))
+        .filter_map(|(dictionary, words)| Some((dictionary, *words.get(word)?)))
+        .min_by_key(|(_, rank)| *rank)
+}
+
+/// The length in chars of the longest word in any dictionary, no longer token can match.
+fn longest_word(user_dictionary: &HashMap<String, usize>) -> usize {
+    dictionaries()
+        .iter()
+        .flat_map(|(_, words)| words.keys())
+        .chain(user_dictionary.keys())
+        .map(|word| word.chars().count())
+        .max()
+        .unwrap_or_default()
+}
+
+fn lowercase(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn dictionary_matches(chars: &[char], user_dictionary: &HashMap<String, usize>) -> Vec<Match> {
+    let lower: Vec<char> = chars.iter().copied().map(lowercase).collect();
+    let longest = longest_word(user_dictionary);
+    let mut matches = Vec::new();
+    for start in 0..chars.len() {
+        for end in start + 1..=chars.len().min(start + longest) {
+            let word: String = lower[start..end].iter().collect();
+            if let Some((dictionary, rank)) = lookup(&word, user_dictionary) {
+                matches.push(Match {
+                    start,
+                    end,
+                    guesses: rank as f64 * uppercase_variations(&chars[start..end]),
+                    pattern: Pattern::Dictionary {
+                        dictionary,
+                        rank,
+                        reversed: false,
+                        l33t: false,
+                    },
+                });
+            }
+        }
+    }
+    matches
+}
+
+fn reversed_dictionary_matches(
+    chars: &[char],
+    user_dictionary: &HashMap<String, usize>,
+) -> Vec<Match> {
+    let n = chars.len();
+    let reversed: Vec<char> = chars.iter().rev().copied().collect();
+    dictionary_matches(&reversed, user_dictionary)
+        .into_iter()
+        .filter_map(|mut m| {
+            let token = &reversed[m.start..m.end];
+            // Palindromes are already found the right way around.
+            if token.iter().eq(token.iter().rev()) {
+                return None;
+            }
+            (m.start, m.end) = (n - m.end, n - m.start);
+            m.guesses *= 2.0;
+            if let Pattern::Dictionary { reversed, .. } = &mut m.pattern {
+                *reversed = true;
+            }
+            Some(m)
+        })
+        .collect()
+}
+
+fn l33t_matches(chars: &[char], user_dictionary: &HashMap<String, usize>) -> Vec<Match> {
+    let lower: Vec<char> = chars.iter().copied().map(lowercase).collect();
+    let longest = longest_word(user_dictionary);
+    let mut matches = Vec::new();
+    for start in 0..lower.len() {
+        for end in start + 2..=lower.len().min(start + longest) {
+            let token = &lower[start..end];
+            let mut best: Option<(Dictionary, usize, f64)> = None;
+            for candidate in unl33t(token) {
+                let word: String = candidate.iter().collect();
+                if let Some((dictionary, rank)) = lookup(&word, user_dictionary) {
+                    let guesses = rank as f64
+                        * uppercase_variations(&chars[start..end])
+                        * l33t_variations(token, &candidate);
+                    let better = match best {
+                        Some((_, _, best)) => guesses < best,
+                        None => true,
+                    };
+                    if better {
+                        best = Some((dictionary, rank, guesses));
+                    }
+                }
+            }
+            if let Some((dictionary, rank, guesses)) = best {
+                matches.push(Match {
+                    start,
+                    end,
+                    guesses,
+                    pattern: Pattern::Dictionary {
+                        dictionary,
+                        rank,
+                        reversed: false,
+                        l33t: true,
+                    },
+                });
+            }
+        }
+    }
+    matches
+}
+
+/// Every way to read the l33t characters in `token` as letters, `token` itself excluded.
+fn unl33t(token: &[char]) -> Vec<Vec<char>> {
+    if !token
+        .iter()
+        .any(|c| L33T_TABLE.iter().any(|(l33t, _)| l33t == c))
+    {
+        return Vec::new();
+    }
+    let mut candidates = vec![Vec::with_capacity(token.len())];
+    for c in token {
+        let letters = L33T_TABLE
+            .iter()
+            .find(|(l33t, _)| l33t == c)
+            .map(|(_, letters)| *letters);
+        match letters {
+            Some(letters) => {
+                candidates = candidates
+                    .iter()
+                    .flat_map(|candidate| {
+                        letters.iter().map(move |letter| {
+                            let mut candidate: Vec<char> = candidate.clone();
+                            candidate.push(*letter);
+                            candidate
+                        })
+                    })
+                    .take(MAX_L33T_CANDIDATES)
+                    .collect();
+            }
+            None => candidates
+                .iter_mut()
+                .for_each(|candidate| candidate.push(*c)),
+        }
+    }
+    candidates
+}
+
+/// How many guesses the substitutions from `candidate` to `token` add.
+fn l33t_variations(token: &[char], candidate: &[char]) -> f64 {
+    let mut substitutions: Vec<(char, char)> = token
+        .iter()
+        .zip(candidate)
+        .filter(|(c, letter)| c != letter)
+        .map(|(c, letter)| (*c, *letter))
+        .collect();
+    substitutions.sort_unstable();
+    substitutions.dedup();
+
+    let mut variations = 1.0;
+    for (l33t, letter) in substitutions {
+        let substituted = token
+            .iter()
+            .zip(candidate)
+            .filter(|(c, l)| **c == l33t && **l == letter)
+            .count();
+        let unsubstituted = token.iter().filter(|c| **c == letter).count();
+        variations *= variations_of(substituted, unsubstituted);
+    }
+    variations
+}
+
+/// How many guesses the capitalization of `token` adds.
+fn uppercase_variations(token: &[char]) -> f64 {
+    let upper = token.iter().filter(|c| c.is_uppercase()).count();
+    let lower = token.iter().filter(|c| c.is_lowercase()).count();
+    if upper == 0 {
+        return 1.0;
+    }
+    let first_only = upper == 1 && token.first().is_some_and(|c| c.is_uppercase());
+    let last_only = upper == 1 && token.last().is_some_and(|c| c.is_uppercase());
+    if lower == 0 || first_only || last_only {
+        return 2.0;
+    }
+    variations_of(upper, lower)
+}
+
+/// The ways `changed` of `changed + unchanged` characters could have been picked, or 2 when all
+/// or none of them were.
+fn variations_of(changed: usize, unchanged: usize) -> f64 {
+    if changed == 0 || unchanged == 0 {
+        return 2.0;
+    }
+    (1..=changed.min(unchanged))
+        .map(|i| n_choose_k(changed + unchanged, i))
+        .sum()
+}
+
+fn n_choose_k(n: usize, k: usize) -> f64 {
+    (1..=k).fold(1.0, |result, i| result * (n + 1 - i) as f64 / i as f64)
+}
+
+fn factorial(n: usize) -> f64 {
+    (2..=n).fold(1.0, |result, i| result * i as f64)
+}
+
+/// The position of every key and the keys at each position.
+struct Keyboard {
+    positions: HashMap<char, ((i32, i32), bool)>,
+    keys: HashMap<(i32, i32), (char, char)>,
+}
+
+fn keyboard() -> &'static Keyboard {
+    static KEYBOARD: OnceLock<Keyboard> = OnceLock::new();
+    KEYBOARD.get_or_init(|| {
+        let mut keyboard = Keyboard {
+            positions: HashMap::new(),
+            keys: HashMap::new(),
+        };
+        for (y, (offset, unshifted, shifted)) in QWERTY.iter().enumerate() {
+            for (x, (key, shifted_key)) in unshifted.chars().zip(shifted.chars()).enumerate() {
+                let position = (x as i32 + offset, y as i32);
+                keyboard.positions.insert(key, (position, false));
+                keyboard.positions.insert(shifted_key, (position, true));
+                keyboard.keys.insert(position, (key, shifted_key));
+            }
+        }
+        keyboard
+    })
+}
+
+impl Keyboard {
+    /// The direction to go from `from` to the neighbouring key `to`, if they are neighbours.
+    fn direction(&self, from: char, to: char) -> Option<usize> {
+        let ((x, y), _) = self.positions.get(&from)?;
+        let (position, _) = self.positions.get(&to)?;
+        NEIGHBOURS
+            .iter()
+            .position(|(dx, dy)| (x + dx, y + dy) == *position)
+    }
+
+    fn is_shifted(&self, c: char) -> bool {
+        self.positions.get(&c).is_some_and(|(_, shifted)| *shifted)
+    }
+
+    fn average_degree(&self) -> f64 {
+        let neighbours: usize = self
+            .keys
+            .keys()
+            .map(|(x, y)| {
+                NEIGHBOURS
+                    .iter()
+                    .filter(|(dx, dy)| self.keys.contains_key(&(x + dx, y + dy)))
+                    .count()
+            })
+            .sum();
+        neighbours as f64 / self.keys.len() as f64
+    }
+}
+
+/// Walks of three or more neighbouring keys, like `qwerty` or `zxcdsa`.
+fn spatial_matches(chars: &[char]) -> Vec<Match> {
+    let keyboard = keyboard();
+    let starting_positions = keyboard.positions.len() as f64;
+    let average_degree = keyboard.average_degree();
+
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start + 1 < chars.len() {
+        let mut end = start + 1;
+        let mut last_direction = None;
+        let mut turns = 0;
+        let mut shifted = usize::from(keyboard.is_shifted(chars[start]));
+        while end < chars.len() {
+            let Some(direction) = keyboard.direction(chars[end - 1], chars[end]) else {
+                break;
+            };
+            if keyboard.is_shifted(chars[end]) {
+                shifted += 1;
+            }
+            if last_direction != Some(direction) {
+                turns += 1;
+                last_direction = Some(direction);
+            }
+            end += 1;
+        }
+
+        let len = end - start;
+        if len > 2 {
+            let mut guesses = 0.0;
+            for i in 2..=len {
+                for j in 1..=turns.min(i - 1) {
+                    guesses += n_choose_k(i - 1, j - 1)
+                        * starting_positions
+                        * average_degree.powi(j as i32);
+                }
+            }
+            if shifted > 0 {
+                guesses *= variations_of(shifted, len - shifted);
+            }
+            matches.push(Match {
+                start,
+                end,
+                guesses,
+                pattern: Pattern::Spatial { turns },
+            });
+        }
+        start = end;
+    }
+    matches
+}
+
+/// Runs of a repeated block, like `aaa` or `abcabc`.
+fn repeat_matches(chars: &[char], user_dictionary: &HashMap<String, usize>) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        // The block repeated over the most characters, the shortest one on a tie.
+        let mut best: Option<(usize, usize)> = None;
+        for block_len in 1..=(chars.len() - start) / 2 {
+            let block = &chars[start..start + block_len];
+            let repeats = chars[start..]
+                .chunks_exact(block_len)
+                .take_while(|chunk| *chunk == block)
+                .count();
+            let better = match best {
+                Some((best_len, best_repeats)) => block_len * repeats > best_len * best_repeats,
+                None => true,
+            };
+            if repeats >= 2 && better {
+                best = Some((block_len, repeats));
+            }
+        }
+
+        match best {
+            Some((block_len, repeats)) => {
+                let block = &chars[start..start + block_len];
+                let (block_guesses, _) = most_guessable_sequence(block, user_dictionary);
+                matches.push(Match {
+                    start,
+                    end: start + block_len * repeats,
+                    guesses: block_guesses * repeats as f64,
+                    pattern: Pattern::Repeat { block_len },
+                });
+                start += block_len * repeats;
+            }
+            None => start += 1,
+        }
+    }
+    matches
+}
+
+/// Runs of three or more characters with the same small step between them, like `abc` or `9753`.
+fn sequence_matches(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut push = |start: usize, end: usize, delta: i64| {
+        let token = &chars[start..end];
+        let same_class = token.iter().all(char::is_ascii_lowercase)
+            || token.iter().all(char::is_ascii_uppercase)
+            || token.iter().all(char::is_ascii_digit);
+        if end - start < 3 || delta == 0 || delta.abs() > 5 || !same_class {
+            return;
+        }
+        let mut guesses = if "aAzZ019".contains(token[0]) {
+            4.0
+        } else if token[0].is_ascii_digit() {
+            10.0
+        } else {
+            26.0
+        };
+        if delta < 0 {
+            guesses *= 2.0;
+        }
+        matches.push(Match {
+            start,
+            end,
+            guesses: guesses * token.len() as f64,
+            pattern: Pattern::Sequence,
+        });
+    };
+
+    let mut start = 0;
+    let mut last_delta = None;
+    for end in 1..chars.len() {
+        let delta = chars[end] as i64 - chars[end - 1] as i64;
+        match last_delta {
+            None => last_delta = Some(delta),
+            Some(last) if last == delta => {}
+            Some(last) => {
+                push(start, end, last);
+                start = end - 1;
+                last_delta = Some(delta);
+            }
+        }
+    }
+    if let Some(last) = last_delta {
+        push(start, chars.len(), last);
+    }
+    matches
+}
+
+/// Years like `1987` and dates like `13/5/87` or `19870513`.
+fn date_matches(chars: &[char]) -> Vec<Match> {
+    let reference_year = 1970 + unix_timestamp() / 31_556_952;
+    let year_guesses = |year: u64| (year.abs_diff(reference_year) as f64).max(MIN_YEAR_SPACE);
+
+    let mut matches = Vec::new();
+    for start in 0..chars.len() {
+        for end in start + 4..=chars.len().min(start + 10) {
+            let token = &chars[start..end];
+            if token.iter().all(char::is_ascii_digit) {
+                let number = |range: std::ops::Range<usize>| -> u64 {
+                    token[range].iter().collect::<String>().parse().unwrap()
+                };
+                if token.len() == 4 && (1900..=2099).contains(&number(0..4)) {
+                    matches.push(Match {
+                        start,
+                        end,
+                        guesses: year_guesses(number(0..4)),
+                        pattern: Pattern::Year,
+                    });
+                }
+                let splits: &[(usize, usize)] = match token.len() {
+                    4 => &[(1, 2), (2, 3)],
+                    5 => &[(1, 3), (2, 3)],
+                    6 => &[(1, 2), (2, 4), (4, 5)],
+                    7 => &[(1, 3), (2, 3), (4, 5), (4, 6)],
+                    8 => &[(2, 4), (4, 6)],
+                    _ => &[],
+                };
+                let year = splits
+                    .iter()
+                    .filter_map(|(k, l)| {
+                        date_year([number(0..*k), number(*k..*l), number(*l..token.len())])
+                    })
+                    .min_by_key(|year| year.abs_diff(reference_year));
+                if let Some(year) = year {
+                    matches.push(Match {
+                        start,
+                        end,
+                        guesses: 365.0 * year_guesses(year),
+                        pattern: Pattern::Date,
+                    });
+                }
+            } else if token.len() >= 6 {
+                let Some(separator) = token.iter().find(|c| !c.is_ascii_digit()) else {
+                    continue;
+                };
+                let token: String = token.iter().collect();
+                let parts: Vec<&str> = token.split(*separator).collect();
+                let valid = DATE_SEPARATORS.contains(*separator)
+                    && parts.len() == 3
+                    && parts.iter().all(|part| {
+                        (1..=4).contains(&part.len()) && part.chars().all(|c| c.is_ascii_digit())
+                    })
+                    && parts[1].len() <= 2;
+                if !valid {
+                    continue;
+                }
+                let parts = [0, 1, 2].map(|i| parts[i].parse().unwrap());
+                if let Some(year) = date_year(parts) {
+                    matches.push(Match {
+                        start,
+                        end,
+                        guesses: 365.0 * year_guesses(year) * 4.0,
+                        pattern: Pattern::Date,
+                    });
+                }
+            }
+        }
+    }
+    matches
+}
+
+/// The year of a date written as day, month and year in any common order, if it's one.
+fn date_year([first, middle, last]: [u64; 3]) -> Option<u64> {
+    if !(1..=31).contains(&middle) {
+        return None;
+    }
+    let is_day_month = |a: u64, b: u64| {
+        ((1..=31).contains(&a) && (1..=12).contains(&b))
+            || ((1..=12).contains(&a) && (1..=31).contains(&b))
+    };
+    let options = [(last, first, middle), (first, middle, last)];
+    // Four digit years first, `11/12/13` is far more likely to be from 2013 than from 2011.
+    options
+        .iter()
+        .find(|(year, a, b)| (1000..=2050).contains(year) && is_day_month(*a, *b))
+        .map(|(year, _, _)| *year)
+        .or_else(|| {
+            options
+                .iter()
+                .find(|(year, a, b)| *year <= 99 && is_day_month(*a, *b))
+                .map(|(year, _, _)| if *year > 50 { 1900 + year } else { 2000 + year })
+        })
+}
+
+fn score(guesses: f64) -> u8 {
+    // A little slack so estimates sitting right on a threshold get the higher score.
+    const DELTA: f64 = 5.0;
+    match guesses {
+        g if g < 1e3 + DELTA => 0,
+        g if g < 1e6 + DELTA => 1,
+        g if g < 1e8 + DELTA => 2,
+        g if g < 1e10 + DELTA => 3,
+        _ => 4,
+    }
+}
+
+fn display_time(seconds: f64) -> String {
+    const MINUTE: f64 = 60.0;
+    const HOUR: f64 = MINUTE * 60.0;
+    const DAY: f64 = HOUR * 24.0;
+    const MONTH: f64 = DAY * 31.0;
+    const YEAR: f64 = MONTH * 12.0;
+    const CENTURY: f64 = YEAR * 100.0;
+
+    let (amount, unit) = match seconds {
+        s if s < 1.0 => return "less than a second".into(),
+        s if s < MINUTE => (s, "second"),
+        s if s < HOUR => (s / MINUTE, "minute"),
+        s if s < DAY => (s / HOUR, "hour"),
+        s if s < MONTH => (s / DAY, "day"),
+        s if s < YEAR => (s / MONTH, "month"),
+        s if s < CENTURY => (s / YEAR, "year"),
+        _ => return "centuries".into(),
+    };
+    let amount = amount.round() as u64;
+    format!("{} {}{}", amount, unit, if amount == 1 { "" } else { "s" })
+}
+
+fn feedback(score: u8, sequence: &[Match], chars: &[char]) -> Feedback {
+    if chars.is_empty() {
+        return Feedback {
+            warning: None,
+            suggestions: vec![
+                "Use a few words, avoid common phrases".into(),
+                "No need for symbols, digits, or uppercase letters".into(),
+            ],
+        };
+    }
+    if score > 2 {
+        return Feedback::default();
+    }
+
+    let mut feedback = sequence
+        .iter()
+        .max_by_key(|m| m.end - m.start)
+        .map(|m| match_feedback(m, sequence.len() == 1, &chars[m.start..m.end]))
+        .unwrap_or_default();
+    feedback.suggestions.insert(
+        0,
+        "Add another word or two. Uncommon words are better.".into(),
+    );
+    feedback
+}
+
+fn match_feedback(m: &Match, only_match: bool, token: &[char]) -> Feedback {
+    let (warning, suggestions): (Option<&str>, Vec<&str>) = match m.pattern {
+        Pattern::Dictionary {
+            dictionary,
+            rank,
+            reversed,
+            l33t,
+        } => {
+            let warning = match dictionary {
+                Dictionary::CommonPasswords if only_match && !reversed && !l33t => {
+                    Some(match rank {
+                        0..=10 => "This is a top-10 common password",
+                        11..=100 => "This is a top-100 common password",
+                        _ => "This is a very common password",
+                    })
+                }
+                Dictionary::CommonPasswords => Some("This is similar to a commonly used password"),
+                Dictionary::EnglishWords if only_match => Some("A word by itself is easy to guess"),
+                Dictionary::EnglishWords => None,
+                Dictionary::UserInputs => {
+                    Some("Passwords based on the entry's details are easy to guess")
+                }
+            };
+
+            let mut suggestions = Vec::new();
+            let letters: Vec<&char> = token.iter().filter(|c| c.is_alphabetic()).collect();
+            if letters.first().is_some_and(|c| c.is_uppercase())
+                && letters.iter().skip(1).all(|c| c.is_lowercase())
+            {
+                suggestions.push("Capitalization doesn't help very much");
+            } else if letters.len() > 1 && letters.iter().all(|c| c.is_uppercase()) {
+                suggestions.push("All-uppercase is almost as easy to guess as all-lowercase");
+            }
+            if reversed && token.len() >= 4 {
+                suggestions.push("Reversed words aren't much harder to guess");
+            }
+            if l33t {
+                suggestions
+                    .push("Predictable substitutions like '@' instead of 'a' don't help very much");
+            }
+            (warning, suggestions)
+        }
+        Pattern::Spatial { turns } => (
+            Some(if turns == 1 {
+                "Straight rows of keys are easy to guess"
+            } else {
+                "Short keyboard patterns are easy to guess"
+            }),
+            vec!["Use a longer keyboard pattern with more turns"],
+        ),
+        Pattern::Repeat { block_len } => (
+            Some(if block_len == 1 {
+                "Repeats like \"aaa\" are easy to guess"
+            } else {
+                "Repeats like \"abcabcabc\" are only slightly harder to guess than \"abc\""
+            }),
+            vec!["Avoid repeated words and characters"],
+        ),
+        Pattern::Sequence => (
+            Some("Sequences like abc or 6543 are easy to guess"),
+            vec!["Avoid sequences"],
+        ),
+        Pattern::Year => (
+            Some("Recent years are easy to guess"),
+            vec![
+                "Avoid recent years",
+                "Avoid years that are associated with you",
+            ],
+        ),
+        Pattern::Date => (
+            Some("Dates are often easy to guess"),
+            vec!["Avoid dates and years that are associated with you"],
+        ),
+        Pattern::BruteForce => (None, Vec::new()),
+    };
+    Feedback {
+        warning: warning.map(String::from),
+        suggestions: suggestions.into_iter().map(String::from).collect(),
+    }
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Estimate how hard `password` is to guess, `user_inputs` are things it shouldn't be based on,
+/// like the username or the vault's name.
+pub fn estimate_password_strength(
+    password: SecretString,
+    user_inputs: Option<Vec<String>>,
+) -> Strength {
+    let user_inputs: Vec<&str> = user_inputs.iter().flatten().map(String::as_str).collect();
+    estimate(password.unsecure(), &user_inputs)
+}
+
+#[test]
+fn test_estimate_strength() {
+    for weak in [
+        "", "password", "P@ssw0rd", "qwerty", "zxcvbnm", "aaaaaaaa", "abcdefg",
+    ] {
+        let strength = estimate(weak, &[]);
+        assert!(strength.score <= 1, "{} scored {}", weak, strength.score);
+    }
+    assert_eq!(
+        estimate("password", &[]).feedback.warning.as_deref(),
+        Some("This is a top-10 common password")
+    );
+    assert_eq!(
+        estimate("13/05/1987", &[]).feedback.warning.as_deref(),
+        Some("Dates are often easy to guess")
+    );
+    assert_eq!(
+        estimate("zxcvfr", &[]).feedback.warning.as_deref(),
+        Some("Short keyboard patterns are easy to guess")
+    );
+
+    let user_input = estimate("spartanvault", &["spartanvault"]);
+    assert_eq!(user_input.score, 0);
+    assert!(estimate("spartanvault", &[]).score > user_input.score);
+
+    // Random words from the embedded list are as strong as their entropy says.
+    let generated = crate::generator::generate_passphrase_with(&Default::default()).unwrap();
+    let passphrase = estimate(generated.password.unsecure(), &[]);
+    assert_eq!(passphrase.score, 4);
+    assert!(passphrase.feedback.warning.is_none());
+    assert_eq!(estimate("x7#Qm!2vLp9@wZ4k", &[]).score, 4);
+    assert_eq!(display_time(3.0 * 3600.0), "3 hours");
+}
@@ -0,0 +1,220 @@
+//! Which logins belong to a page. The url of every login and its alternative urls are matched
+//! against the page's url the way the login's [UrlMatch] says, and every match is graded by how
+//! close it is so the best logins can be offered first.
+//!
+//! Registrable domains come from the Public Suffix List bundled with the app, so
+//! `accounts.example.co.uk` and `www.example.co.uk` share a domain while `alice.github.io` and
+//! `bob.github.io` don't.
+
+use std::{iter, str::FromStr, sync::OnceLock};
+
+use publicsuffix::{List, Psl};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    entry::{EntryData, VaultEntry},
+    error::{AppError, AppResult},
+};
+
+const PUBLIC_SUFFIX_LIST: &str = include_str!("../resources/public_suffix_list.dat");
+
+static PUBLIC_SUFFIXES: OnceLock<List> = OnceLock::new();
+
+/// How the urls of a login are matched against a page.
+///
+/// New variants must only ever be appended, bincode stores the variant index.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UrlMatch {
+    /// The page is on the same registrable domain, e.g. `login.example.com` for `example.com`.
+    #[default]
+    Domain,
+    /// The page has the same host.
+    Host,
+    /// The page has the same host and port.
+    HostAndPort,
+    /// The page's url starts with the login's url.
+    StartsWith,
+    /// The login's url is a regular expression the page's url has to match.
+    Regex,
+    /// The login is never offered for a page.
+    Never,
+}
+
+/// How close a login's url is to a page, better matches are listed first.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchQuality {
+    /// A different host on the same registrable domain.
+    Domain,
+    /// The same host on a different scheme or port.
+    Host,
+    /// The same scheme, host and port.
+    Origin,
+    /// The same origin and path.
+    Exact,
+}
+
+impl UrlMatch {
+    /// Check `url` can be matched this way, only regular expressions can be invalid.
+    pub fn validate(&self, url: &str) -> AppResult<()> {
+        if *self == UrlMatch::Regex {
+            Regex::new(url).map_err(|e| {
+                AppError::InvalidEntry(format!("{} isn't a valid regular expression: {}", url, e))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// How close the login url `url` is to the page at `page`, `None` if it doesn't match.
+    ///
+    /// Pages are matched by scheme as well: a login saved for `https` is never offered on a plain
+    /// `http` page, the other way around is fine.
+    pub fn matches(&self, url: &str, page: &Url) -> Option<MatchQuality> {
+        let required = match self {
+            UrlMatch::Never => return None,
+            UrlMatch::Regex => {
+                // A pattern can't be graded, it's as specific as the user made it.
+                let regex = Regex::new(url).ok()?;
+                return regex.is_match(page.as_str()).then_some(MatchQuality::Host);
+            }
+            UrlMatch::StartsWith => {
+                let parsed = parse_url(url);
+                let prefix = parsed.as_ref().map_or(url, Url::as_str);
+                if !page.as_str().starts_with(prefix) {
+                    return None;
+                }
+                return Some(
+                    parsed
+                        .and_then(|url| closeness(&url, page))
+                        .unwrap_or(MatchQuality::Origin),
+                );
+            }
+            UrlMatch::Domain => MatchQuality::Domain,
+            UrlMatch::Host => MatchQuality::Host,
+            UrlMatch::HostAndPort => MatchQuality::Origin,
+        };
+        let url = parse_url(url)?;
+        if url.scheme() != page.scheme() && !(url.scheme() == "http" && page.scheme() == "https") {
+            return None;
+        }
+        closeness(&url, page).filter(|quality| *quality >= required)
+    }
+}
+
+/// How close the login `entry` is to the page at `page`, the best match of its url and
+/// alternative urls. `None` if it isn't a login or none of them match.
+pub fn match_entry(entry: &VaultEntry, page: &Url) -> Option<MatchQuality> {
+    let EntryData::Login { url, .. } = &entry.data else {
+        return None;
+    };
+    iter::once(url)
+        .chain(&entry.alternative_urls)
+        .filter_map(|url| entry.url_match.matches(url, page))
+        .max()
+}
+
+/// Logins are often saved without a scheme, e.g. `example.com`, those are taken to be https.
+fn parse_url(url: &str) -> Option<Url> {
+    let url = url.trim();
+    Url::parse(url)
+        .ok()
+        .filter(Url::has_host)
+        .or_else(|| Url::parse(&format!("https://{}", url)).ok())
+}
+
+fn closeness(url: &Url, page: &Url) -> Option<MatchQuality> {
+    if url.host_str()? != page.host_str()? {
+        let domain = registrable_domain(url)?;
+        return (Some(domain) == registrable_domain(page)).then_some(MatchQuality::Domain);
+    }
+    if url.scheme() != page.scheme() || url.port_or_known_default() != page.port_or_known_default()
+    {
+        return Some(MatchQuality::Host);
+    }
+    if url.path() != page.path() {
+        return Some(MatchQuality::Origin);
+    }
+    Some(MatchQuality::Exact)
+}
+
+/// The public suffix plus one label, `None` for ip addresses and hosts that are a public suffix
+/// themselves, e.g. `localhost`.
+fn registrable_domain(url: &Url) -> Option<&str> {
+    let host = url.domain()?.trim_end_matches('.');
+    let list = PUBLIC_SUFFIXES.get_or_init(|| {
+        List::from_str(PUBLIC_SUFFIX_LIST).expect("the bundled public suffix list is valid")
+    });
+    let domain = list.domain(host.as_bytes())?;
+    std::str::from_utf8(domain.as_bytes()).ok()
+}
+
+#[test]
+fn test_url_match() {
+    let page = |url: &str| Url::parse(url).unwrap();
+    let login = page("https://accounts.example.co.uk/login?next=/");
+
+    assert_eq!(
+        UrlMatch::Domain.matches("www.example.co.uk", &login),
+        Some(MatchQuality::Domain)
+    );
+    assert_eq!(
+        UrlMatch::Domain.matches("https://other.co.uk", &login),
+        None
+    );
+    assert_eq!(
+        UrlMatch::Domain.matches("http://accounts.example.co.uk", &login),
+        Some(MatchQuality::Host)
+    );
+    // Logins saved for https aren't offered on http pages.
+    assert_eq!(
+        UrlMatch::Domain.matches("https://example.com", &page("http://example.com")),
+        None
+    );
+    // Every github.io user has a domain of their own.
+    assert_eq!(
+        UrlMatch::Domain.matches("https://alice.github.io", &page("https://bob.github.io")),
+        None
+    );
+    assert_eq!(
+        UrlMatch::Domain.matches("http://localhost:8080", &page("http://localhost:3000")),
+        Some(MatchQuality::Host)
+    );
+
+    assert_eq!(
+        UrlMatch::Host.matches("https://example.co.uk", &login),
+        None
+    );
+    assert_eq!(
+        UrlMatch::Host.matches("https://accounts.example.co.uk:8443", &login),
+        Some(MatchQuality::Host)
+    );
+    assert_eq!(
+        UrlMatch::HostAndPort.matches("https://accounts.example.co.uk:8443", &login),
+        None
+    );
+    assert_eq!(
+        UrlMatch::HostAndPort.matches("https://accounts.example.co.uk:443/login", &login),
+        Some(MatchQuality::Exact)
+    );
+
+    assert_eq!(
+        UrlMatch::StartsWith.matches("https://accounts.example.co.uk/log", &login),
+        Some(MatchQuality::Origin)
+    );
+    assert_eq!(
+        UrlMatch::StartsWith.matches("https://accounts.example.co.uk/admin", &login),
+        None
+    );
+    assert_eq!(
+        UrlMatch::Regex.matches(r"^https://[a-z]+\.example\.co\.uk/", &login),
+        Some(MatchQuality::Host)
+    );
+    assert_eq!(UrlMatch::Regex.matches(r"^http://", &login), None);
+    assert!(UrlMatch::Regex.validate("(unclosed").is_err());
+    assert!(UrlMatch::Domain.validate("(unclosed").is_ok());
+    assert_eq!(
+        UrlMatch::Never.matches("https://accounts.example.co.uk/login", &login),
+        None
+    );
+}
@@ -1,28 +1,48 @@
 use std::{
     collections::HashMap,
-    fs::{self, File},
-    io::{BufReader, Read},
+    fs, mem,
     path::{Path, PathBuf},
     sync::Mutex,
 };
 
 use aes_gcm::{
-    aead::{generic_array::GenericArray, Aead},
+    aead::{generic_array::GenericArray, Aead, Payload},
     Aes256Gcm, KeyInit, Nonce,
 };
-use bincode::ErrorKind;
-use pbkdf2::pbkdf2_hmac_array;
-use rand::{seq::SliceRandom, Rng};
-use serde::{Deserialize, Serialize};
-use sha2::{digest::typenum, Sha256};
+use rand::Rng;
+use secstr::SecStr;
+use sha2::digest::{crypto_common::InvalidLength, typenum};
 use tauri::Manager;
+use url::Url;
+use uuid::Uuid;
 
-use crate::{error::EncryptionResult, state::ConfigState};
+use crate::{
+    audit::{self, AuditOptions, AuditReport},
+    backup::{self, BackupInfo},
+    breach::{self, BreachDatabase, BreachedPassword},
+    capture::{Capture, PendingSave, PendingSaves, SaveKind},
+    entry::{
+        unix_timestamp, EntryData, EntryDetails, EntryKind, ListedEntry, PasswordHistoryItem,
+        VaultEntry,
+    },
+    error::{AppError, AppResult, EncryptionError, EncryptionResult},
+    folder::{FolderTree, Folders},
+    format::{self, VaultContents, VaultFile, VaultHeader},
+    kdf::{Kdf, DEFAULT_UNLOCK_TIME},
+    otp::{OtpSecret, TotpCode},
+    recovery::{decode_recovery_key, encode_recovery_key},
+    secret::{key_from_slice, random_key, SecretKey, SecretString},
+    state::ConfigState,
+    strength,
+    url_match::{self, MatchQuality, UrlMatch},
+};
 
-const SALT_SIZE: usize = 16;
-const NONCE_SIZE: usize = 12;
-const KEY_SIZE: usize = 32;
+pub const SALT_SIZE: usize = 16;
+pub const NONCE_SIZE: usize = 12;
+pub const KEY_SIZE: usize = 32;
 
+/// Anything that needs both the `VaultManager` and the config has to lock the `VaultManager`
+/// first, otherwise two threads can end up waiting on each other.
 pub struct VaultManagerState(pub Mutex<VaultManager>);
 
 impl VaultManagerState {
@@ -60,50 +80,84 @@ impl VaultManager {
         self.active_vault_name.as_deref()
     }
 
-    pub fn get_active_vault(&mut self) -> Option<&mut Vault> {
-        let x = self.active_vault_name.as_deref()?;
-        self.vaults.get_mut(x)
+    /// Returns the active vault, or an error if no vault was opened or it's no longer unlocked.
+    pub fn get_active_vault(&mut self) -> AppResult<&mut Vault> {
+        let name = self
+            .active_vault_name
+            .as_deref()
+            .ok_or(AppError::NoActiveVault)?;
+        self.vaults
+            .get_mut(name)
+            .ok_or_else(|| AppError::VaultLocked(name.into()))
     }
 
-    pub fn get_vaults(&self) -> &HashMap<String, Vault> {
-        &self.vaults
+    /// Drop the active vault from memory, wiping its keys and entries. It stays the active vault,
+    /// so it has to be unlocked with [Vault::read] again before it can be used.
+    pub fn lock_active_vault(&mut self) -> AppResult<()> {
+        let name = self
+            .active_vault_name
+            .as_deref()
+            .ok_or(AppError::NoActiveVault)?;
+        self.vaults.remove(name);
+        Ok(())
+    }
+
+    /// Drop the active vault from memory, wiping its keys and entries, and deactivate it.
+    pub fn close_active_vault(&mut self) -> AppResult<()> {
+        let name = self
+            .active_vault_name
+            .take()
+            .ok_or(AppError::NoActiveVault)?;
+        self.vaults.remove(&name);
+        Ok(())
+    }
+
+    /// Drop every open vault from memory, used when the app shuts down.
+    pub fn lock_all(&mut self) {
+        self.vaults.clear();
     }
-}
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-struct VaultHeader {
-    signature: [u8; 1],
-    salt: [u8; SALT_SIZE],
-    master_password_nonce: [u8; NONCE_SIZE],
-    recovery_key_nonce: [u8; NONCE_SIZE],
-    master_password_key: Vec<u8>,
-    recovery_key: Vec<u8>,
+    /// Update the number of backups kept for every open vault.
+    pub fn set_backup_count(&mut self, backup_count: usize) {
+        for vault in self.vaults.values_mut() {
+            vault.set_backup_count(backup_count);
+        }
+    }
 }
 
-#[derive(Debug, Default)]
+/// An unlocked vault. The internal key and the entries' passwords are wiped from memory when
+/// it's dropped.
+#[derive(Debug)]
 pub struct Vault {
     // Header info
     header: VaultHeader,
     // Do not serialize
-    internal_key: [u8; KEY_SIZE],
+    internal_key: SecretKey,
     path: PathBuf,
+    backup_count: usize,
     // Encrypted Data
-    vault_entries: HashMap<String, VaultEntry>,
+    vault_entries: HashMap<Uuid, VaultEntry>,
+    folders: Folders,
+    // Only kept while the vault is unlocked
+    pending_saves: PendingSaves,
 }
 
 impl Vault {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         path: &Path,
+        kdf: Kdf,
         salt: [u8; SALT_SIZE],
         master_password_nonce: [u8; NONCE_SIZE],
         recovery_key_nonce: [u8; NONCE_SIZE],
-        internal_key: [u8; KEY_SIZE],
+        internal_key: SecretKey,
         master_password_key: Vec<u8>,
         recovery_key: Vec<u8>,
     ) -> Self {
         Self {
             header: VaultHeader {
-                signature: [0xED],
+                kdf,
+                cipher: Default::default(),
                 salt,
                 master_password_nonce,
                 recovery_key_nonce,
@@ -112,104 +166,1011 @@ impl Vault {
             },
             path: path.into(),
             internal_key,
+            backup_count: 0,
             vault_entries: HashMap::default(),
+            folders: Folders::default(),
+            pending_saves: PendingSaves::default(),
         }
     }
 
-    pub fn read(path: &Path, master_password: &str) -> Result<Self, Box<ErrorKind>> {
-        let mut reader: BufReader<File> = BufReader::new(File::open(path)?);
+    /// Read and decrypt the vault at `path`. Vaults written by older versions are upgraded in memory
+    /// and will be written back using the current format on the next [Vault::write].
+    ///
+    /// The salt, KDF parameters and wrapped key are needed to unwrap the internal key before
+    /// anything can be authenticated, so if they were modified this fails with
+    /// [AppError::WrongPassword] rather than [AppError::Integrity].
+    pub fn read(path: &Path, master_password: &str) -> AppResult<Self> {
+        let bytes = fs::read(path)?;
+        let file = VaultFile::parse(&bytes)?;
+        file.header.kdf.check_limits()?;
 
-        let header: VaultHeader = bincode::deserialize_from(&mut reader)?;
-        let nonce: [u8; NONCE_SIZE] = bincode::deserialize_from(&mut reader)?;
-        let mut ciphertext_bytes = Vec::new();
-        let x = reader.read_to_end(&mut ciphertext_bytes).unwrap();
-        println!("Got {} bytes", x);
+        let (derived_key, _) =
+            derive_encryption_key(master_password, file.header.kdf, Some(file.header.salt))?;
+        let internal_key = unwrap_internal_key(
+            &file.header.master_password_key,
+            &derived_key,
+            file.header.master_password_nonce,
+        )?;
 
-        println!("Header: {:#?}", header);
-        println!("Nonce: {:#?}", nonce);
-        println!("Ciphertext: {:#?}", ciphertext_bytes);
+        Self::decrypt(path, file, internal_key)
+    }
 
-        let (derived_key, _) = derive_encryption_key(master_password, Some(header.salt));
-        let internal_key = decrypt_ciphertext_of_size(
-            &header.master_password_key,
-            derived_key,
-            header.master_password_nonce,
-        )
-        .unwrap();
+    /// Read and decrypt the vault at `path` using the recovery key handed out when the vault was
+    /// created instead of the master password.
+    pub fn read_with_recovery_key(path: &Path, recovery_key: &SecretKey) -> AppResult<Self> {
+        let bytes = fs::read(path)?;
+        let file = VaultFile::parse(&bytes)?;
+        // Setting the new master password derives a key with these.
+        file.header.kdf.check_limits()?;
+
+        let internal_key = unwrap_internal_key(
+            &file.header.recovery_key,
+            recovery_key,
+            file.header.recovery_key_nonce,
+        )?;
+
+        Self::decrypt(path, file, internal_key)
+    }
 
-        let decrypted_bytes = decrypt_ciphertext(&ciphertext_bytes, internal_key, nonce).unwrap();
-        let vault_entries: HashMap<String, VaultEntry> = bincode::deserialize(&decrypted_bytes).unwrap();
-        println!("Decrypt: {:#?}", vault_entries);
+    /// Decrypt the entries of a parsed vault file with the (already unwrapped) internal key.
+    fn decrypt(path: &Path, file: VaultFile, internal_key: SecretKey) -> AppResult<Self> {
+        let contents = decrypt_contents(&file, &internal_key)?;
 
         Ok(Self {
-            header,
+            header: file.header,
             path: path.into(),
             internal_key,
-            vault_entries,
+            backup_count: 0,
+            vault_entries: contents.entries,
+            folders: contents.folders,
+            pending_saves: PendingSaves::default(),
+        })
+    }
+
+    /// Add `vault_entry` under its id and return that id.
+    fn add_vault_entry(&mut self, vault_entry: VaultEntry) -> Uuid {
+        let id = vault_entry.id;
+        self.vault_entries.insert(id, vault_entry);
+        id
+    }
+
+    /// Validate `vault_entry`, add it and write the vault. The entry isn't kept if the vault
+    /// couldn't be written.
+    pub fn save_new_vault_entry(&mut self, vault_entry: VaultEntry) -> AppResult<Uuid> {
+        vault_entry.validate()?;
+        let id = self.add_vault_entry(vault_entry);
+        if let Err(e) = self.write() {
+            self.vault_entries.remove(&id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// Replace the title and data (and the details if they're given) of the entry with the
+    /// given `id` and write the vault.
+    ///
+    /// The entry is left unchanged if it fails validation or the vault couldn't be written.
+    pub fn update_vault_entry(
+        &mut self,
+        id: Uuid,
+        title: String,
+        data: EntryData,
+        details: Option<EntryDetails>,
+    ) -> AppResult<()> {
+        self.modify_vault_entry(id, |entry| {
+            entry.update(title, data, details);
+            Ok(())
+        })
+    }
+
+    /// Replace the username, password and url (and the details if they're given) of the login
+    /// with the given `id` and write the vault, see [Vault::update_vault_entry].
+    pub fn update_login(
+        &mut self,
+        id: Uuid,
+        username: String,
+        password: SecretString,
+        url: String,
+        details: Option<EntryDetails>,
+    ) -> AppResult<()> {
+        self.modify_vault_entry(id, |entry| {
+            entry.update_login(username, password, url, details)
+        })
+    }
+
+    /// Apply `modify` to a copy of the entry with the given `id`, validate it and only replace
+    /// the entry if the vault was written successfully.
+    fn modify_vault_entry(
+        &mut self,
+        id: Uuid,
+        modify: impl FnOnce(&mut VaultEntry) -> AppResult<()>,
+    ) -> AppResult<()> {
+        let entry = self
+            .vault_entries
+            .get_mut(&id)
+            .ok_or(AppError::UnknownEntry(id))?;
+        let mut updated = entry.clone();
+        modify(&mut updated)?;
+        updated.validate()?;
+
+        let old_entry = mem::replace(entry, updated);
+        if let Err(e) = self.write() {
+            self.vault_entries.insert(id, old_entry);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Roll the login with the given `id` back to the `index`th password of its history and
+    /// write the vault, see [VaultEntry::restore_password].
+    pub fn restore_password(&mut self, id: Uuid, index: usize) -> AppResult<()> {
+        self.modify_vault_entry(id, |entry| entry.restore_password(index))
+    }
+
+    /// Record that the credentials of the entry with the given `id` were just used, see
+    /// [Vault::use_vault_entry].
+    pub fn mark_entry_used(&mut self, id: Uuid) -> AppResult<()> {
+        self.use_vault_entry(id, |entry| {
+            entry.mark_used();
+            Ok(())
+        })
+    }
+
+    /// Apply `modify` to the entry with the given `id` as it's being used and write the vault.
+    ///
+    /// Using an entry doesn't rotate the backups, otherwise a few autofills would push every real
+    /// change out of them. The entry is left unchanged if `modify` fails or the vault couldn't be
+    /// written.
+    fn use_vault_entry<T>(
+        &mut self,
+        id: Uuid,
+        modify: impl FnOnce(&mut VaultEntry) -> AppResult<T>,
+    ) -> AppResult<T> {
+        let entry = self
+            .vault_entries
+            .get_mut(&id)
+            .ok_or(AppError::UnknownEntry(id))?;
+        let mut updated = entry.clone();
+        let value = modify(&mut updated)?;
+
+        let old_entry = mem::replace(entry, updated);
+        let written = self
+            .encode()
+            .and_then(|bytes| Ok(backup::write_without_backup(&self.path, &bytes)?));
+        if let Err(e) = written {
+            self.vault_entries.insert(id, old_entry);
+            return Err(e);
+        }
+        Ok(value)
+    }
+
+    /// Move the entry with the given `id` into `folder`, or out of any folder if it's `None`, and
+    /// write the vault.
+    pub fn set_entry_folder(&mut self, id: Uuid, folder: Option<Uuid>) -> AppResult<()> {
+        if let Some(folder) = folder {
+            if !self.folders.contains(folder) {
+                return Err(AppError::UnknownFolder(folder));
+            }
+        }
+        self.modify_vault_entry(id, |entry| {
+            entry.folder = folder;
+            entry.modified = unix_timestamp();
+            Ok(())
+        })
+    }
+
+    /// Give the entry with the given `id` an OTP secret, or remove it if `otp` is `None`, and
+    /// write the vault.
+    pub fn set_entry_otp(&mut self, id: Uuid, otp: Option<OtpSecret>) -> AppResult<()> {
+        self.modify_vault_entry(id, |entry| {
+            entry.otp = otp;
+            entry.modified = unix_timestamp();
+            Ok(())
+        })
+    }
+
+    /// The next HOTP code of the entry with the given `id`. The advanced counter is written
+    /// before the code is returned, so a code is never handed out twice. Like any other use of
+    /// the entry that doesn't rotate the backups, see [Vault::use_vault_entry].
+    pub fn next_hotp(&mut self, id: Uuid) -> AppResult<SecretString> {
+        self.use_vault_entry(id, |entry| {
+            entry
+                .otp
+                .as_mut()
+                .ok_or_else(|| AppError::InvalidEntry("The entry has no OTP secret".into()))?
+                .next_hotp()
+        })
+    }
+
+    pub fn get_vault_entry(&self, id: Uuid) -> AppResult<&VaultEntry> {
+        self.vault_entries
+            .get(&id)
+            .ok_or(AppError::UnknownEntry(id))
+    }
+
+    /// The logins matching the page at `url`, see [url_match]. The best matches come first and
+    /// equally good ones are ordered most recently used first.
+    pub fn logins_for_url(&self, url: &str) -> AppResult<Vec<&VaultEntry>> {
+        let page = Url::parse(url)
+            .map_err(|_| AppError::InvalidRequest(format!("{} isn't a valid url", url)))?;
+        let mut logins: Vec<(MatchQuality, &VaultEntry)> = self
+            .vault_entries
+            .values()
+            .filter_map(|entry| Some((url_match::match_entry(entry, &page)?, entry)))
+            .collect();
+        logins.sort_by(|(a_quality, a), (b_quality, b)| {
+            (b_quality, b.last_used, &a.title).cmp(&(a_quality, a.last_used, &b.title))
+        });
+        Ok(logins.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    /// Set how the login with the given `id` is matched against pages and its alternative urls,
+    /// and write the vault.
+    pub fn set_entry_url_match(
+        &mut self,
+        id: Uuid,
+        url_match: UrlMatch,
+        alternative_urls: Vec<String>,
+    ) -> AppResult<()> {
+        self.modify_vault_entry(id, |entry| {
+            entry.url_match = url_match;
+            entry.alternative_urls = alternative_urls;
+            entry.modified = unix_timestamp();
+            Ok(())
+        })
+    }
+
+    /// Classify a login submitted on the page at `url` and queue it for the user to save, unless
+    /// it's already saved. A login of the page (see [Vault::logins_for_url]) with the same
+    /// username is updated rather than duplicated.
+    pub fn capture_login(
+        &mut self,
+        url: String,
+        username: String,
+        password: SecretString,
+    ) -> AppResult<Capture> {
+        if password.unsecure().is_empty() {
+            return Err(AppError::InvalidRequest(
+                "The password can't be empty".into(),
+            ));
+        }
+        let same_username: Vec<&VaultEntry> = self
+            .logins_for_url(&url)?
+            .into_iter()
+            .filter(|entry| match &entry.data {
+                EntryData::Login {
+                    username: saved, ..
+                } => *saved == username,
+                _ => false,
+            })
+            .collect();
+        let kind = match same_username
+            .iter()
+            .find(|entry| entry.password() == Some(&password))
+        {
+            Some(entry) => SaveKind::Duplicate { entry: entry.id },
+            None => match same_username.first() {
+                Some(entry) => SaveKind::Update { entry: entry.id },
+                None => SaveKind::New,
+            },
+        };
+        let pending = match kind {
+            SaveKind::Duplicate { .. } => None,
+            _ => Some(self.pending_saves.push(kind, url, username, password)),
+        };
+        Ok(Capture { kind, pending })
+    }
+
+    /// Logins waiting for the user to save them, oldest first.
+    pub fn pending_saves(&self) -> &[PendingSave] {
+        self.pending_saves.list()
+    }
+
+    /// Save the pending save `id` and write the vault: add the new login, or give the login it
+    /// updates the new username and password, keeping the old password in its history. Returns
+    /// the id of the saved login.
+    ///
+    /// The save stays pending if the vault couldn't be written.
+    pub fn accept_pending_save(&mut self, id: Uuid) -> AppResult<Uuid> {
+        let save = self.pending_saves.get(id)?.clone();
+        let entry = match save.kind {
+            SaveKind::New => {
+                self.save_new_vault_entry(VaultEntry::new(save.username, save.password, save.url))?
+            }
+            SaveKind::Update { entry } | SaveKind::Duplicate { entry } => {
+                self.modify_vault_entry(entry, |login| {
+                    let EntryData::Login { url, .. } = &login.data else {
+                        return Err(AppError::InvalidEntry("The entry isn't a login".into()));
+                    };
+                    // The page the form was on is often deeper than the url that's saved.
+                    let url = url.clone();
+                    login.update_login(save.username, save.password, url, None)
+                })?;
+                entry
+            }
+        };
+        self.pending_saves.remove(id)?;
+        Ok(entry)
+    }
+
+    pub fn dismiss_pending_save(&mut self, id: Uuid) -> AppResult<()> {
+        self.pending_saves.remove(id).map(|_| ())
+    }
+
+    pub fn folder_tree(&self) -> FolderTree {
+        self.folders
+            .tree(self.vault_entries.values().map(|entry| entry.folder))
+    }
+
+    /// Create a folder called `name` inside `parent` (at the top if it's `None`), write the
+    /// vault and return the new folder's id.
+    pub fn create_folder(&mut self, name: String, parent: Option<Uuid>) -> AppResult<Uuid> {
+        self.modify_folders(|folders, _| folders.create(name, parent))
+    }
+
+    pub fn rename_folder(&mut self, id: Uuid, name: String) -> AppResult<()> {
+        self.modify_folders(|folders, _| folders.rename(id, name))
+    }
+
+    /// Move the folder `id` into `parent` (to the top if it's `None`) and write the vault.
+    pub fn move_folder(&mut self, id: Uuid, parent: Option<Uuid>) -> AppResult<()> {
+        self.modify_folders(|folders, _| folders.move_folder(id, parent))
+    }
+
+    /// Delete the folder `id` and write the vault. Its entries and subfolders move up into the
+    /// deleted folder's parent.
+    pub fn delete_folder(&mut self, id: Uuid) -> AppResult<()> {
+        self.modify_folders(|folders, entries| {
+            let parent = folders.delete(id)?;
+            for entry in entries.values_mut() {
+                if entry.folder == Some(id) {
+                    entry.folder = parent;
+                }
+            }
+            Ok(())
         })
     }
 
-    fn add_vault_entry(&mut self, entry_title: String, vault_entry: VaultEntry) {
-        self.vault_entries.insert(entry_title, vault_entry);
+    /// Apply `modify` to the folders (and entries) and write the vault. Both are rolled back if
+    /// `modify` fails or the vault couldn't be written.
+    fn modify_folders<T>(
+        &mut self,
+        modify: impl FnOnce(&mut Folders, &mut HashMap<Uuid, VaultEntry>) -> AppResult<T>,
+    ) -> AppResult<T> {
+        let old_folders = self.folders.clone();
+        let old_entries = self.vault_entries.clone();
+        let result = modify(&mut self.folders, &mut self.vault_entries).and_then(|value| {
+            self.write()?;
+            Ok(value)
+        });
+        if result.is_err() {
+            self.folders = old_folders;
+            self.vault_entries = old_entries;
+        }
+        result
+    }
+
+    /// Remove the entry with the given `id`, write the vault and return the removed entry.
+    ///
+    /// The entry is put back if the vault couldn't be written.
+    pub fn remove_vault_entry(&mut self, id: Uuid) -> AppResult<VaultEntry> {
+        let entry = self
+            .vault_entries
+            .remove(&id)
+            .ok_or(AppError::UnknownEntry(id))?;
+        if let Err(e) = self.write() {
+            self.vault_entries.insert(id, entry);
+            return Err(e);
+        }
+        Ok(entry)
+    }
+
+    /// Re-wrap the internal key with a key derived from `new_password`.
+    ///
+    /// Only the header changes, the vault entries stay encrypted with the same internal key.
+    /// A failed write leaves both the file and the in-memory vault unlockable with `old_password`.
+    pub fn change_master_password(
+        &mut self,
+        old_password: &str,
+        new_password: &str,
+    ) -> AppResult<()> {
+        // Make sure the old password actually unlocks this vault before replacing it.
+        let (old_key, _) =
+            derive_encryption_key(old_password, self.header.kdf, Some(self.header.salt))?;
+        let internal_key = unwrap_internal_key(
+            &self.header.master_password_key,
+            &old_key,
+            self.header.master_password_nonce,
+        )?;
+        if internal_key != self.internal_key {
+            return Err(AppError::WrongPassword);
+        }
+
+        self.set_master_password(new_password)
+    }
+
+    /// Wrap the internal key with a key derived from `new_password` and write the vault, without
+    /// checking the current master password. Used after unlocking with the recovery key.
+    ///
+    /// The new header is only kept if the vault was written successfully.
+    pub fn set_master_password(&mut self, new_password: &str) -> AppResult<()> {
+        // Vaults created before Argon2id was supported get upgraded while we're at it.
+        let kdf = match self.header.kdf {
+            Kdf::Pbkdf2HmacSha256 { .. } => Kdf::calibrate(DEFAULT_UNLOCK_TIME)?,
+            kdf => kdf,
+        };
+        let (new_key, salt) = derive_encryption_key(new_password, kdf, None)?;
+        let (master_password_nonce, master_password_key) =
+            encrypt_plaintext(self.internal_key.unsecure(), &new_key, &[])?;
+
+        let new_header = VaultHeader {
+            kdf,
+            salt,
+            master_password_nonce,
+            master_password_key,
+            ..self.header.clone()
+        };
+        let old_header = mem::replace(&mut self.header, new_header);
+        if let Err(e) = self.write() {
+            self.header = old_header;
+            return Err(e);
+        }
+        Ok(())
     }
 
-    pub fn write(&self) -> Result<(), Box<ErrorKind>> {
-        let mut bytes: Vec<u8> = Vec::new();
+    /// Encrypt the vault entries and write the vault to its path using the current file format.
+    ///
+    /// The vault is written to a temporary file next to it first and then renamed over the
+    /// existing vault, so the vault on disk is always either the old or the new version.
+    pub fn write(&self) -> AppResult<()> {
+        let bytes = self.encode()?;
+        backup::write_with_backups(&self.path, &bytes, self.backup_count)?;
+        Ok(())
+    }
 
-        bytes.extend(bincode::serialize(&self.header)?);
+    /// Encrypt the vault entries into the bytes of a vault file using the current file format.
+    fn encode(&self) -> AppResult<Vec<u8>> {
+        let entries_bytes = format::serialize_contents(&self.vault_entries, &self.folders)?;
+        let mut bytes = format::serialize_header(&self.header)?;
+        // Authenticate the header along with the entries.
+        let (nonce, ciphertext) =
+            encrypt_plaintext(entries_bytes.unsecure(), &self.internal_key, &bytes)?;
 
-        let entries_bytes = bincode::serialize(&self.vault_entries)?;
-        let (nonce, ciphertext) = encrypt_plaintext(&entries_bytes, self.internal_key).unwrap();
         bytes.extend(nonce);
         bytes.extend(ciphertext);
-        fs::write(&self.path, bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn set_backup_count(&mut self, backup_count: usize) {
+        self.backup_count = backup_count;
+    }
+
+    pub fn list_backups(&self) -> AppResult<Vec<BackupInfo>> {
+        Ok(backup::list_backups(&self.path, self.backup_count)?)
+    }
+
+    /// Replace the entries and folders of this vault with the ones from backup `index` and write
+    /// the vault.
+    ///
+    /// The master password (and the rest of the header) stays the same, the backup only has to
+    /// be encrypted with the same internal key. The current version of the vault becomes backup
+    /// `1`, so a restore can be undone by restoring that one. The vault is left unchanged if it
+    /// couldn't be written.
+    pub fn restore_backup(&mut self, index: usize) -> AppResult<()> {
+        let bytes = fs::read(backup::backup_path(&self.path, index))?;
+        let file = VaultFile::parse(&bytes)?;
+        let contents = decrypt_contents(&file, &self.internal_key)?;
+        let old_entries = mem::replace(&mut self.vault_entries, contents.entries);
+        let old_folders = mem::replace(&mut self.folders, contents.folders);
+        if let Err(e) = self.write() {
+            self.vault_entries = old_entries;
+            self.folders = old_folders;
+            return Err(e);
+        }
         Ok(())
     }
 }
 
+#[cfg(test)]
+pub(crate) fn test_vault(path: &Path, password: &str) -> Vault {
+    // Cheap parameters so tests don't spend their time in the KDF.
+    let kdf = Kdf::Argon2id {
+        memory_kib: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
+    let internal_key = random_key();
+    let (password_key, salt) = derive_encryption_key(password, kdf, None).unwrap();
+    let (nonce, wrapped_key) =
+        encrypt_plaintext(internal_key.unsecure(), &password_key, &[]).unwrap();
+
+    Vault::new(
+        path,
+        kdf,
+        salt,
+        nonce,
+        [0; NONCE_SIZE],
+        internal_key,
+        wrapped_key,
+        Vec::new(),
+    )
+}
+
+#[cfg(test)]
+fn test_entry() -> VaultEntry {
+    VaultEntry::new(
+        "user".into(),
+        "password".into(),
+        "https://example.com".into(),
+    )
+}
+
+#[test]
+fn test_write_read_roundtrip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vault");
+    let mut vault = test_vault(&path, "password");
+    let id = vault.add_vault_entry(VaultEntry::new(
+        "user".into(),
+        "hunter2".into(),
+        "https://example.com".into(),
+    ));
+    // A second account on the same site doesn't replace the first one.
+    vault.add_vault_entry(VaultEntry::new(
+        "other user".into(),
+        "hunter3".into(),
+        "https://example.com".into(),
+    ));
+    vault.write().unwrap();
+
+    let bytes = fs::read(&path).unwrap();
+    assert!(bytes.starts_with(&format::MAGIC));
+
+    let read = Vault::read(&path, "password").unwrap();
+    assert_eq!(read.header.kdf, vault.header.kdf);
+    assert_eq!(read.vault_entries.len(), 2);
+    assert!(matches!(
+        &read.vault_entries[&id].data,
+        EntryData::Login { password, .. } if password.unsecure() == "hunter2"
+    ));
+}
+
+#[test]
+fn test_change_master_password() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vault");
+    let mut vault = test_vault(&path, "old password");
+    let id = vault.add_vault_entry(test_entry());
+    vault.write().unwrap();
+    let old_salt = vault.header.salt;
+
+    assert!(matches!(
+        vault.change_master_password("wrong", "new password"),
+        Err(AppError::WrongPassword)
+    ));
+    vault
+        .change_master_password("old password", "new password")
+        .unwrap();
+
+    assert!(matches!(
+        Vault::read(&path, "old password"),
+        Err(AppError::WrongPassword)
+    ));
+    let read = Vault::read(&path, "new password").unwrap();
+    assert_eq!(read.internal_key, vault.internal_key);
+    assert_ne!(read.header.salt, old_salt);
+    assert!(read.vault_entries.contains_key(&id));
+}
+
 #[test]
-fn test_read() {
-    let _ = Vault::read(Path::new("/home/loucas/.config/com.spartankey/vault"), "password").unwrap();
+fn test_check_master_password() {
+    assert!(matches!(
+        check_master_password("password1", "personal", 3),
+        Err(AppError::WeakPassword { minimum: 3, .. })
+    ));
+    assert!(check_master_password("password1", "personal", 0).is_ok());
+    assert!(check_master_password("correct horse battery staple", "personal", 3).is_ok());
 }
 
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
-pub struct VaultEntry {
-    username: String,
-    password: String,
-    url: String,
+#[test]
+fn test_read_with_recovery_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vault");
+    let mut vault = test_vault(&path, "forgotten");
+    let recovery_key = random_key();
+    let (nonce, wrapped_key) =
+        encrypt_plaintext(vault.internal_key.unsecure(), &recovery_key, &[]).unwrap();
+    vault.header.recovery_key_nonce = nonce;
+    vault.header.recovery_key = wrapped_key;
+    let id = vault.add_vault_entry(test_entry());
+    vault.write().unwrap();
+
+    let mut recovered = Vault::read_with_recovery_key(&path, &recovery_key).unwrap();
+    assert!(recovered.vault_entries.contains_key(&id));
+    recovered.set_master_password("new password").unwrap();
+
+    let read = Vault::read(&path, "new password").unwrap();
+    assert_eq!(read.internal_key, vault.internal_key);
+    // The recovery key keeps working after the master password was replaced.
+    assert_eq!(read.header.recovery_key, vault.header.recovery_key);
+}
+
+#[test]
+fn test_tampered_header() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vault");
+    let vault = test_vault(&path, "password");
+    vault.write().unwrap();
+
+    // Change a header field that isn't needed to unwrap the internal key.
+    let file = VaultFile::parse(&fs::read(&path).unwrap()).unwrap();
+    let mut header = file.header.clone();
+    header.recovery_key_nonce[0] ^= 1;
+    let mut bytes = format::serialize_header(&header).unwrap();
+    bytes.extend(file.nonce);
+    bytes.extend(&file.ciphertext);
+    fs::write(&path, bytes).unwrap();
+
+    let result = Vault::read(&path, "password");
+    assert!(matches!(result, Err(AppError::Integrity)));
+    assert!(matches!(
+        Vault::read(&path, "wrong"),
+        Err(AppError::WrongPassword)
+    ));
+}
+
+#[test]
+fn test_tampered_key_wrapping() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vault");
+    let vault = test_vault(&path, "password");
+    vault.write().unwrap();
+    let file = VaultFile::parse(&fs::read(&path).unwrap()).unwrap();
+
+    // Changing what's needed to unwrap the internal key looks like the wrong password.
+    let tamper = |modify: fn(&mut VaultHeader)| {
+        let mut header = file.header.clone();
+        modify(&mut header);
+        let mut bytes = format::serialize_header(&header).unwrap();
+        bytes.extend(file.nonce);
+        bytes.extend(&file.ciphertext);
+        fs::write(&path, bytes).unwrap();
+        Vault::read(&path, "password")
+    };
+    assert!(matches!(
+        tamper(|header| header.salt[0] ^= 1),
+        Err(AppError::WrongPassword)
+    ));
+    assert!(matches!(
+        tamper(|header| header.master_password_key[0] ^= 1),
+        Err(AppError::WrongPassword)
+    ));
+}
+
+#[test]
+fn test_restore_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vault");
+    let mut vault = test_vault(&path, "password");
+    vault.set_backup_count(3);
+
+    let first = vault.add_vault_entry(test_entry());
+    vault.write().unwrap();
+    vault.add_vault_entry(test_entry());
+    vault.write().unwrap();
+    vault
+        .change_master_password("password", "new password")
+        .unwrap();
+    assert_eq!(vault.list_backups().unwrap().len(), 2);
+    // Using an entry doesn't push out a backup.
+    vault.mark_entry_used(first).unwrap();
+    assert_eq!(vault.list_backups().unwrap().len(), 2);
+    assert!(
+        Vault::read(&path, "new password").unwrap().vault_entries[&first]
+            .last_used
+            .is_some()
+    );
+
+    // Backup 2 only has the first entry, but restoring it keeps the new master password.
+    vault.restore_backup(2).unwrap();
+    let read = Vault::read(&path, "new password").unwrap();
+    assert_eq!(read.vault_entries.keys().collect::<Vec<_>>(), vec![&first]);
+}
+
+#[test]
+fn test_next_hotp() {
+    use crate::otp::OtpKind;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vault");
+    let mut vault = test_vault(&path, "password");
+    vault.set_backup_count(3);
+    let id = vault.save_new_vault_entry(test_entry()).unwrap();
+    let otp =
+        OtpSecret::parse("otpauth://hotp/Example:alice?secret=JBSWY3DPEHPK3PXP&counter=5").unwrap();
+    vault.set_entry_otp(id, Some(otp)).unwrap();
+    let backups = vault.list_backups().unwrap().len();
+
+    // Handing out codes doesn't push out a backup, but the counter is written every time.
+    vault.next_hotp(id).unwrap();
+    vault.next_hotp(id).unwrap();
+    assert_eq!(vault.list_backups().unwrap().len(), backups);
+    let read = Vault::read(&path, "password").unwrap();
+    assert_eq!(
+        read.vault_entries[&id].otp.as_ref().unwrap().kind,
+        OtpKind::Hotp { counter: 7 }
+    );
+}
+
+#[test]
+fn test_lock_active_vault() {
+    let mut vault_manager = VaultManagerState::new().0.into_inner().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vault");
+    vault_manager.add_and_activate_vault("vault", test_vault(&path, "password"));
+    assert!(vault_manager.get_active_vault().is_ok());
+
+    vault_manager.lock_active_vault().unwrap();
+    assert!(vault_manager.get_vault("vault").is_none());
+    assert!(matches!(
+        vault_manager.get_active_vault(),
+        Err(AppError::VaultLocked(_))
+    ));
+
+    vault_manager.close_active_vault().unwrap();
+    assert!(matches!(
+        vault_manager.get_active_vault(),
+        Err(AppError::NoActiveVault)
+    ));
+}
+
+#[test]
+fn test_folders() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vault");
+    let mut vault = test_vault(&path, "password");
+    let mut entry = test_entry();
+    entry.modified = 0;
+    let id = vault.add_vault_entry(entry);
+    let work = vault.create_folder("Work".into(), None).unwrap();
+    let servers = vault.create_folder("Servers".into(), Some(work)).unwrap();
+    vault.set_entry_folder(id, Some(servers)).unwrap();
+    assert!(vault.get_vault_entry(id).unwrap().modified > 0);
+    assert!(matches!(
+        vault.set_entry_folder(id, Some(Uuid::new_v4())),
+        Err(AppError::UnknownFolder(_))
+    ));
+
+    let read = Vault::read(&path, "password").unwrap();
+    let tree = read.folder_tree();
+    assert_eq!(tree.unfiled_entry_count, 0);
+    assert_eq!(tree.folders[0].total_entry_count, 1);
+    assert_eq!(tree.folders[0].children[0].entry_count, 1);
+
+    // The entry moves up with the deleted folder's subfolders.
+    vault.delete_folder(servers).unwrap();
+    let read = Vault::read(&path, "password").unwrap();
+    assert_eq!(read.vault_entries[&id].folder, Some(work));
+    assert_eq!(read.folder_tree().folders[0].entry_count, 1);
+}
+
+#[test]
+fn test_update_delete_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vault");
+    let mut vault = test_vault(&path, "password");
+    let id = vault.add_vault_entry(test_entry());
+    let other = vault.add_vault_entry(test_entry());
+
+    vault
+        .update_login(
+            id,
+            "new user".into(),
+            "new password".into(),
+            "https://example.org".into(),
+            Some(EntryDetails {
+                notes: "notes".into(),
+                tags: vec!["work".into()],
+                ..Default::default()
+            }),
+        )
+        .unwrap();
+    assert!(matches!(
+        vault.update_login(
+            id,
+            "user".into(),
+            "".into(),
+            "https://example.org".into(),
+            None
+        ),
+        Err(AppError::InvalidEntry(_))
+    ));
+    assert!(matches!(
+        vault.update_login(
+            Uuid::new_v4(),
+            "user".into(),
+            "password".into(),
+            "https://example.org".into(),
+            None
+        ),
+        Err(AppError::UnknownEntry(_))
+    ));
+
+    let read = Vault::read(&path, "password").unwrap();
+    assert!(matches!(
+        &read.vault_entries[&id].data,
+        EntryData::Login { username, url, .. }
+            if username == "new user" && url == "https://example.org"
+    ));
+    assert_eq!(read.vault_entries[&id].notes.unsecure(), "notes");
+    assert_eq!(read.vault_entries[&id].tags, vec!["work"]);
+
+    // Changing the kind of an entry goes through the same validation.
+    assert!(matches!(
+        vault.update_vault_entry(
+            other,
+            "note".into(),
+            EntryData::SecureNote { text: "".into() },
+            None
+        ),
+        Err(AppError::InvalidEntry(_))
+    ));
+    vault
+        .update_vault_entry(
+            other,
+            "note".into(),
+            EntryData::SecureNote {
+                text: "text".into(),
+            },
+            None,
+        )
+        .unwrap();
+    assert_eq!(vault.vault_entries[&other].kind(), EntryKind::SecureNote);
+
+    let removed = vault.remove_vault_entry(other).unwrap();
+    assert_eq!(removed.id, other);
+    assert!(matches!(
+        vault.remove_vault_entry(other),
+        Err(AppError::UnknownEntry(_))
+    ));
+    let read = Vault::read(&path, "password").unwrap();
+    assert_eq!(read.vault_entries.keys().collect::<Vec<_>>(), vec![&id]);
+}
+
+#[test]
+fn test_logins_for_url() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vault");
+    let mut vault = test_vault(&path, "password");
+    let login = |url: &str| VaultEntry::new("user".into(), "password".into(), url.into());
+    let origin = vault.add_vault_entry(login("https://example.com"));
+    let mut used = login("https://example.com/login?next=/");
+    used.last_used = Some(1);
+    let used = vault.add_vault_entry(used);
+    let exact = vault.add_vault_entry(login("https://example.com/account"));
+    let subdomain = vault.add_vault_entry(login("https://www.example.com"));
+    let mut alternative = login("https://example.org");
+    alternative.alternative_urls = vec!["https://example.com:8443".into()];
+    let alternative = vault.add_vault_entry(alternative);
+    let mut never = login("https://example.com/account");
+    never.url_match = UrlMatch::Never;
+    vault.add_vault_entry(never);
+    vault.add_vault_entry(login("https://example.com.evil.net"));
+    vault.add_vault_entry(login("not a url"));
+
+    let ids: Vec<Uuid> = vault
+        .logins_for_url("https://example.com/account")
+        .unwrap()
+        .iter()
+        .map(|entry| entry.id)
+        .collect();
+    assert_eq!(ids, vec![exact, used, origin, alternative, subdomain]);
+    assert!(matches!(
+        vault.logins_for_url("example.com"),
+        Err(AppError::InvalidRequest(_))
+    ));
+}
+
+#[test]
+fn test_capture_login() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vault");
+    let mut vault = test_vault(&path, "password");
+    let capture = |vault: &mut Vault, username: &str, password: &str| {
+        vault
+            .capture_login(
+                "https://example.com/login".into(),
+                username.into(),
+                password.into(),
+            )
+            .unwrap()
+    };
+
+    let new = capture(&mut vault, "user", "first");
+    assert_eq!(new.kind, SaveKind::New);
+    // Capturing the same login again only keeps the latest.
+    let new = capture(&mut vault, "user", "second").pending.unwrap();
+    assert_eq!(vault.pending_saves().len(), 1);
+    let id = vault.accept_pending_save(new).unwrap();
+    assert!(vault.pending_saves().is_empty());
+    assert_eq!(
+        vault.get_vault_entry(id).unwrap().url_match,
+        UrlMatch::Domain
+    );
+
+    assert_eq!(
+        capture(&mut vault, "user", "second"),
+        Capture {
+            kind: SaveKind::Duplicate { entry: id },
+            pending: None
+        }
+    );
+    let other_user = capture(&mut vault, "other", "second");
+    assert_eq!(other_user.kind, SaveKind::New);
+    vault
+        .dismiss_pending_save(other_user.pending.unwrap())
+        .unwrap();
+
+    let update = capture(&mut vault, "user", "third");
+    assert_eq!(update.kind, SaveKind::Update { entry: id });
+    vault.accept_pending_save(update.pending.unwrap()).unwrap();
+    let entry = vault.get_vault_entry(id).unwrap();
+    assert_eq!(entry.password().unwrap().unsecure(), "third");
+    assert_eq!(entry.password_history[0].password.unsecure(), "second");
+    assert!(matches!(
+        &entry.data,
+        EntryData::Login { url, .. } if url == "https://example.com/login"
+    ));
+    assert!(matches!(
+        vault.accept_pending_save(update.pending.unwrap()),
+        Err(AppError::UnknownPendingSave(_))
+    ));
+    assert_eq!(vault.vault_entries.len(), 1);
 }
 
 #[tauri::command]
 /// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
 /// Creates a new vault with the specified `vault_name`, `vault_path` which will be encrypted by the `master_password`
+///
+/// Returns the vault's recovery key, this is the only time it's available so it must be shown to the user.
+///
+/// Fails with [AppError::WeakPassword] if the master password's strength score is below the
+/// configured minimum.
 pub fn create_new_vault(
     vault_name: String,
     vault_path: String,
-    master_password: String,
+    master_password: SecretString,
     app_handle: tauri::AppHandle<tauri::Wry>,
-) {
-    println!("vault_name: {}", vault_name);
-    println!("vault_path: {}", vault_path);
-    println!("master_password: {}", master_password);
+) -> AppResult<SecretString> {
+    let config_state: tauri::State<ConfigState> = app_handle.state();
+    let minimum = config_state
+        .state
+        .lock()
+        .unwrap()
+        .get_min_master_password_score();
+    check_master_password(master_password.unsecure(), &vault_name, minimum)?;
+
+    // Pick the Argon2id parameters for this vault based on how fast this machine is.
+    let kdf = Kdf::calibrate(DEFAULT_UNLOCK_TIME)?;
 
-    // Generate an obnoxious string for the encryption key.
-    let internal_master_key = derive_encryption_key(&generate_password(KEY_SIZE), None).0;
+    // The internal key never leaves the vault, so it can be random bytes rather than derived.
+    let internal_master_key = random_key();
 
     // Now derive an encryption key from the master password
     let (master_password_key, master_password_key_salt) =
-        derive_encryption_key(&master_password, None);
+        derive_encryption_key(master_password.unsecure(), kdf, None)?;
 
     // And use the encryption key derived from the master_password to encrypt the internal_master_key
     let (mp_encrypted_internal_master_key_nonce, mp_encrypted_internal_master_key) =
-        encrypt_plaintext(&internal_master_key, master_password_key).unwrap();
+        encrypt_plaintext(internal_master_key.unsecure(), &master_password_key, &[])?;
 
-    // TODO: Write the recovery key to a file (maybe noeof?)
-    // Derive a random encryption key, this is the recovery key
-    let (recovery_key, _) = derive_encryption_key(&generate_password(KEY_SIZE), None);
+    // Generate a random encryption key, this is the recovery key. It's only ever shown to the
+    // user once, right after the vault is created.
+    let recovery_key = random_key();
 
-    // Encrypt the internal master key again, but this time using the randomly derived encryption key.
+    // Encrypt the internal master key again, but this time using the random recovery key.
     let (rk_encrypted_internal_master_key_nonce, rk_encrypted_internal_master_key) =
-        encrypt_plaintext(&internal_master_key, recovery_key).unwrap();
+        encrypt_plaintext(internal_master_key.unsecure(), &recovery_key, &[])?;
 
     // Get the vault manager
     let vault_manager_state: tauri::State<VaultManagerState> = app_handle
@@ -218,11 +1179,11 @@ pub fn create_new_vault(
     let mut vault_manager = vault_manager_state.0.lock().unwrap();
 
     let path = Path::new(&vault_path);
-    println!("Creating vault at path: {}", vault_path);
-    
+
     // Create a new vault, add it to the vault manager and activate it
-    let vault = Vault::new(
+    let mut vault = Vault::new(
         path,
+        kdf,
         master_password_key_salt,
         mp_encrypted_internal_master_key_nonce,
         rk_encrypted_internal_master_key_nonce,
@@ -230,218 +1191,795 @@ pub fn create_new_vault(
         mp_encrypted_internal_master_key,
         rk_encrypted_internal_master_key,
     );
+    let backup_count = config_state.state.lock().unwrap().get_backup_count();
+    vault.set_backup_count(backup_count);
+
     // Write the vault to the vault path
-    vault.write().unwrap();
+    vault.write()?;
 
     vault_manager.add_and_activate_vault(&vault_name, vault);
 
     // Add the vault mapping to the config
-    let config_state: tauri::State<ConfigState> = app_handle.state();
     config_state.add_vault(&vault_name, path);
-    config_state.write().expect("Error writing config to file.");
-    println!("Done!");
+    config_state.write()?;
+
+    Ok(encode_recovery_key(&recovery_key))
 }
 
 #[tauri::command]
 /// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
-/// Adds a new entry to the currently active vault
+/// Adds a new entry to the currently active vault and returns the id it was given.
+/// `details` holds the notes, custom fields, tags and favorite flag and may be left out.
 pub fn add_entry(
     url: String,
     username: String,
-    password: String,
+    password: SecretString,
+    details: Option<EntryDetails>,
     app_handle: tauri::AppHandle<tauri::Wry>,
-) {
+) -> AppResult<Uuid> {
     // Get the vault manager state and add a vault entry to it
     let vault_manager_state: tauri::State<VaultManagerState> = app_handle
         .try_state()
         .expect("`VaultManager` should already be managed");
     let mut vault_manager = vault_manager_state.0.lock().unwrap();
 
-    if let Some(vault) = vault_manager.get_active_vault() {
-        vault.add_vault_entry(
-            url.clone(),
-            VaultEntry {
-                username,
-                password,
-                url,
-            },
-        );
-        vault.write().unwrap();
-    }
+    let vault = vault_manager.get_active_vault()?;
+    let mut entry = VaultEntry::new(username, password, url);
+    entry.set_details(details.unwrap_or_default());
+    vault.save_new_vault_entry(entry)
 }
 
 #[tauri::command]
 /// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
-/// Returns all the entries in the currently active vault
-pub fn get_active_vault_entries(app_handle: tauri::AppHandle<tauri::Wry>) -> Vec<VaultEntry> {
+/// Replaces the url, username and password of the login `id` in the currently active vault.
+/// The notes, custom fields, tags and favorite flag are only replaced if `details` is given.
+pub fn update_entry(
+    id: Uuid,
+    url: String,
+    username: String,
+    password: SecretString,
+    details: Option<EntryDetails>,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<()> {
     let vault_manager_state: tauri::State<VaultManagerState> = app_handle
         .try_state()
         .expect("`VaultManager` should already be managed");
     let mut vault_manager = vault_manager_state.0.lock().unwrap();
 
-    if let Some(vault) = vault_manager.get_active_vault() {
-        return vault.vault_entries.values().map(|entry| entry.clone()).collect();
-    }
-    Vec::new()
+    let vault = vault_manager.get_active_vault()?;
+    vault.update_login(id, username, password, url, details)
 }
 
 #[tauri::command]
 /// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
-/// Returns a vec of all the known vault names.
-pub fn get_vaults(app_handle: tauri::AppHandle<tauri::Wry>) -> Vec<String> {
-    let config_state: tauri::State<ConfigState> = app_handle.state();
-    let config = config_state.state.lock().unwrap();
-    config.get_vault_names()
+/// Adds a new entry of any kind to the currently active vault and returns the id it was given.
+/// `details` holds the notes, custom fields, tags and favorite flag and may be left out.
+pub fn add_item(
+    title: String,
+    data: EntryData,
+    details: Option<EntryDetails>,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<Uuid> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    let vault = vault_manager.get_active_vault()?;
+    let mut entry = VaultEntry::with_data(title, data);
+    entry.set_details(details.unwrap_or_default());
+    vault.save_new_vault_entry(entry)
 }
 
 #[tauri::command]
 /// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
-/// Tries to read a vault called `name` from disk, decrypt it, and set it to the active vault.
-pub fn open_vault(name: String, password: String, app_handle: tauri::AppHandle<tauri::Wry>) {
+/// Replaces the title and data of the entry `id` in the currently active vault, the entry may
+/// change its kind. The notes, custom fields, tags and favorite flag are only replaced if
+/// `details` is given.
+pub fn update_item(
+    id: Uuid,
+    title: String,
+    data: EntryData,
+    details: Option<EntryDetails>,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<()> {
     let vault_manager_state: tauri::State<VaultManagerState> = app_handle
         .try_state()
         .expect("`VaultManager` should already be managed");
     let mut vault_manager = vault_manager_state.0.lock().unwrap();
 
-    let config_state: tauri::State<ConfigState> = app_handle.state();
-    let config = config_state.state.lock().unwrap();
-    // TODO: First check if the vault manager already has the vault and retrieve it from there
-    // TODO: Otherwise we'll read the vault from disk.
-    let path = config
-        .get_path(&name)
-        .expect(&format!("No vault entry for {}", name));
-    println!("Opening: {}", name);
-    let vault = Vault::read(path.as_path(), &password).unwrap();
-    vault_manager.add_and_activate_vault(&name, vault);
+    let vault = vault_manager.get_active_vault()?;
+    vault.update_vault_entry(id, title, data, details)
 }
 
-/// Generates a random password that satisfies the following password requirements:
-/// - At least one uppercase letter
-/// - At least one digit
-/// - At least one special character
-///
-/// Randomly generate how many of each char there should be, then randomly select that
-/// many characters from the valid characters slices and put into a vec.
-///
-/// Finally, shuffle the vec and convert to utf8 ascii characters
-fn generate_password(password_size: usize) -> String {
-    let lowercase_chars = "abcdefghijklmnopqrstuvwxyz".as_bytes();
-    let uppercase_chars = "ABCDEFGHIJKLMNOPQRSTYVWXYZ".as_bytes();
-    let special_chars = "!@#$%^&*".as_bytes();
-    let digits = "01234567890".as_bytes();
-
-    // Randomly generate the number of each type of character.
-    let mut rng = rand::thread_rng();
-    let num_lowercase = rng.gen_range(1..password_size - 3);
-    let num_uppercase = rng.gen_range(1..password_size - num_lowercase - 2);
-    let num_digits = rng.gen_range(1..password_size - num_lowercase - num_uppercase - 1);
-    let num_special = password_size - (num_lowercase + num_uppercase + num_digits);
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Returns the folder tree of the currently active vault with the number of entries in every
+/// folder
+pub fn get_folder_tree(app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<FolderTree> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
 
-    // Again randomly generate which characters in that set of characters to use.
-    let mut bytes: Vec<u8> = Vec::with_capacity(password_size);
-    for _ in 0..num_lowercase {
-        bytes.push(lowercase_chars[rng.gen_range(0..lowercase_chars.len())]);
-    }
+    Ok(vault_manager.get_active_vault()?.folder_tree())
+}
 
-    for _ in 0..num_uppercase {
-        bytes.push(uppercase_chars[rng.gen_range(0..uppercase_chars.len())]);
-    }
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Creates a folder called `name` inside `parent` (at the top of the currently active vault if
+/// it's left out) and returns the new folder's id
+pub fn create_folder(
+    name: String,
+    parent: Option<Uuid>,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<Uuid> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
 
-    for _ in 0..num_digits {
-        bytes.push(digits[rng.gen_range(0..digits.len())]);
-    }
+    vault_manager
+        .get_active_vault()?
+        .create_folder(name, parent)
+}
 
-    for _ in 0..num_special {
-        bytes.push(special_chars[rng.gen_range(0..special_chars.len())]);
-    }
-    bytes.shuffle(&mut rng);
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Renames the folder `id` in the currently active vault
+pub fn rename_folder(
+    id: Uuid,
+    name: String,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
 
-    // Not possible to be a non-utf8 character
-    String::from_utf8(bytes).unwrap()
+    vault_manager.get_active_vault()?.rename_folder(id, name)
 }
 
-/// Derive the encryption key from the master password and user's email address.
-/// The email is treated as an "add" to make the hash tougher to break and is concatenated with the
-/// master_password to form the "password" which the encryption key is derived from.
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Moves the folder `id` in the currently active vault into `parent`, or to the top if it's
+/// left out
+pub fn move_folder(
+    id: Uuid,
+    parent: Option<Uuid>,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    vault_manager.get_active_vault()?.move_folder(id, parent)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Deletes the folder `id` from the currently active vault, its entries and subfolders move up
+/// into its parent
+pub fn delete_folder(id: Uuid, app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    vault_manager.get_active_vault()?.delete_folder(id)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Moves the entry `id` in the currently active vault into `folder`, or out of any folder if
+/// it's left out
+pub fn set_entry_folder(
+    id: Uuid,
+    folder: Option<Uuid>,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    vault_manager
+        .get_active_vault()?
+        .set_entry_folder(id, folder)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Sets the OTP secret of the entry `id` in the currently active vault from an `otpauth://` uri
+/// or a base32 secret, or removes it if `otp` is left out
+pub fn set_entry_otp(
+    id: Uuid,
+    otp: Option<SecretString>,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<()> {
+    let otp = otp
+        .map(|otp| OtpSecret::parse(otp.unsecure()))
+        .transpose()?;
+
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    vault_manager.get_active_vault()?.set_entry_otp(id, otp)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Sets how the login `id` in the currently active vault is matched against pages and the other
+/// urls it's used on
+pub fn set_entry_url_match(
+    id: Uuid,
+    url_match: UrlMatch,
+    alternative_urls: Vec<String>,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    vault_manager
+        .get_active_vault()?
+        .set_entry_url_match(id, url_match, alternative_urls)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Returns the current TOTP code of the entry `id` in the currently active vault and the
+/// seconds left until it changes
+pub fn get_totp(id: Uuid, app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<TotpCode> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    let vault = vault_manager.get_active_vault()?;
+    let entry = vault
+        .vault_entries
+        .get(&id)
+        .ok_or(AppError::UnknownEntry(id))?;
+    entry
+        .otp
+        .as_ref()
+        .ok_or_else(|| AppError::InvalidEntry("The entry has no OTP secret".into()))?
+        .totp(unix_timestamp())
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Returns the next HOTP code of the entry `id` in the currently active vault and advances its
+/// counter
+pub fn get_hotp(id: Uuid, app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<SecretString> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    vault_manager.get_active_vault()?.next_hotp(id)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Returns the previous passwords of the login `id` in the currently active vault, most recent
+/// first
+pub fn get_password_history(
+    id: Uuid,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<Vec<PasswordHistoryItem>> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    let vault = vault_manager.get_active_vault()?;
+    let entry = vault
+        .vault_entries
+        .get(&id)
+        .ok_or(AppError::UnknownEntry(id))?;
+    Ok(entry.password_history.clone())
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Rolls the login `id` in the currently active vault back to the `index`th password of its
+/// history (`0` is the most recent). The current password is kept in the history.
+pub fn restore_password(
+    id: Uuid,
+    index: usize,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    vault_manager
+        .get_active_vault()?
+        .restore_password(id, index)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Records that the credentials of the entry `id` were just used, e.g. copied to the clipboard
+pub fn mark_entry_used(id: Uuid, app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    vault_manager.get_active_vault()?.mark_entry_used(id)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Deletes the entry `id` from the currently active vault and returns the deleted entry
+pub fn delete_entry(id: Uuid, app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<VaultEntry> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    let vault = vault_manager.get_active_vault()?;
+    vault.remove_vault_entry(id)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Returns all the entries in the currently active vault, or only the entries of one `kind`,
+/// each with the strength score of its password.
+pub fn get_active_vault_entries(
+    kind: Option<EntryKind>,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<Vec<ListedEntry>> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    let vault = vault_manager.get_active_vault()?;
+    Ok(vault
+        .vault_entries
+        .values()
+        .filter(|entry| kind.is_none() || kind == Some(entry.kind()))
+        .map(ListedEntry::from)
+        .collect())
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Returns the logins of the currently active vault matching the page at `url`, best matches
+/// first
+pub fn get_matching_entries(
+    url: String,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<Vec<ListedEntry>> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    let vault = vault_manager.get_active_vault()?;
+    Ok(vault
+        .logins_for_url(&url)?
+        .into_iter()
+        .map(ListedEntry::from)
+        .collect())
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Returns the logins captured by the browser extension that are waiting to be saved to the
+/// currently active vault, oldest first
+pub fn get_pending_saves(app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<Vec<PendingSave>> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    Ok(vault_manager.get_active_vault()?.pending_saves().to_vec())
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Saves the pending save `id` to the currently active vault, either as a new login or as the new
+/// password of the login it updates. Returns the id of the saved login
+pub fn accept_pending_save(id: Uuid, app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<Uuid> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    vault_manager.get_active_vault()?.accept_pending_save(id)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Drops the pending save `id` without saving it
+pub fn dismiss_pending_save(id: Uuid, app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    vault_manager.get_active_vault()?.dismiss_pending_save(id)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Audits the logins of the currently active vault for reused, weak and old passwords and plain
+/// `http://` urls. The default options are used if `options` is omitted.
+pub fn audit_vault(
+    options: Option<AuditOptions>,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<AuditReport> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    let vault = vault_manager.get_active_vault()?;
+    Ok(audit::audit(
+        &vault.vault_entries,
+        &options.unwrap_or_default(),
+        unix_timestamp(),
+    ))
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Looks up the login passwords of the currently active vault in the breach database at
+/// `database_path`, either a HIBP SHA-1 dump ordered by hash or an index built from one. Returns
+/// the compromised logins, most often breached first.
+pub fn check_breached_passwords(
+    database_path: String,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<Vec<BreachedPassword>> {
+    let mut database = BreachDatabase::open(Path::new(&database_path))?;
+
+    // Only the hashes are needed, so the vault isn't kept locked while searching the file.
+    let hashes: Vec<(Uuid, breach::PasswordHash)> = {
+        let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+            .try_state()
+            .expect("`VaultManager` should already be managed");
+        let mut vault_manager = vault_manager_state.0.lock().unwrap();
+        let vault = vault_manager.get_active_vault()?;
+        vault
+            .vault_entries
+            .values()
+            .filter_map(|entry| match &entry.data {
+                EntryData::Login { password, .. } if !password.unsecure().is_empty() => {
+                    Some((entry.id, breach::hash_password(password.unsecure())))
+                }
+                _ => None,
+            })
+            .collect()
+    };
+
+    let mut breached = Vec::new();
+    for (id, hash) in hashes {
+        let count = database.lookup(&hash)?;
+        if count > 0 {
+            breached.push(BreachedPassword { id, count });
+        }
+    }
+    breached.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.id.cmp(&b.id)));
+    Ok(breached)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Changes the master password of the currently active vault after checking `old_password`.
+/// Only the internal key is re-encrypted, the vault entries are left as they are. The new
+/// password needs the configured minimum strength score.
+pub fn change_master_password(
+    old_password: SecretString,
+    new_password: SecretString,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    let config_state: tauri::State<ConfigState> = app_handle.state();
+    let minimum = config_state
+        .state
+        .lock()
+        .unwrap()
+        .get_min_master_password_score();
+    let name = vault_manager
+        .get_active_vault_name()
+        .ok_or(AppError::NoActiveVault)?;
+    check_master_password(new_password.unsecure(), name, minimum)?;
+
+    let vault = vault_manager.get_active_vault()?;
+    vault.change_master_password(old_password.unsecure(), new_password.unsecure())
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Returns a vec of all the known vault names.
+pub fn get_vaults(app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<Vec<String>> {
+    let config_state: tauri::State<ConfigState> = app_handle.state();
+    let config = config_state.state.lock().unwrap();
+    Ok(config.get_vault_names())
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Tries to read a vault called `name` from disk, decrypt it, and set it to the active vault.
+pub fn open_vault(
+    name: String,
+    password: SecretString,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    let config_state: tauri::State<ConfigState> = app_handle.state();
+    let config = config_state.state.lock().unwrap();
+    // TODO: First check if the vault manager already has the vault and retrieve it from there
+    // TODO: Otherwise we'll read the vault from disk.
+    let path = config
+        .get_path(&name)
+        .ok_or_else(|| AppError::UnknownVault(name.clone()))?;
+    let mut vault = Vault::read(path.as_path(), password.unsecure())?;
+    vault.set_backup_count(config.get_backup_count());
+    vault_manager.add_and_activate_vault(&name, vault);
+    Ok(())
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Unlocks the vault called `name` with its `recovery_key` and immediately replaces the forgotten
+/// master password with `new_password`, then sets it as the active vault. The new password needs
+/// the configured minimum strength score.
+pub fn open_vault_with_recovery_key(
+    name: String,
+    recovery_key: SecretString,
+    new_password: SecretString,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<()> {
+    let recovery_key =
+        decode_recovery_key(recovery_key.unsecure()).ok_or(AppError::InvalidRecoveryKey)?;
+
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    let config_state: tauri::State<ConfigState> = app_handle.state();
+    let config = config_state.state.lock().unwrap();
+    let path = config
+        .get_path(&name)
+        .ok_or_else(|| AppError::UnknownVault(name.clone()))?;
+    check_master_password(
+        new_password.unsecure(),
+        &name,
+        config.get_min_master_password_score(),
+    )?;
+
+    let mut vault = Vault::read_with_recovery_key(path.as_path(), &recovery_key)?;
+    vault.set_backup_count(config.get_backup_count());
+    vault.set_master_password(new_password.unsecure())?;
+    vault_manager.add_and_activate_vault(&name, vault);
+    Ok(())
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Returns the backups of the currently active vault that exist on disk, most recent first.
+pub fn list_backups(app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<Vec<BackupInfo>> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    vault_manager.get_active_vault()?.list_backups()
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Restores the entries of the currently active vault from backup `index`.
+pub fn restore_backup(index: usize, app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    vault_manager.get_active_vault()?.restore_backup(index)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Locks the currently active vault, wiping its keys and entries from memory. It has to be
+/// opened with `open_vault` again before it can be used.
+pub fn lock_vault(app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    vault_manager.lock_active_vault()
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Closes the currently active vault, wiping its keys and entries from memory.
+pub fn close_vault(app_handle: tauri::AppHandle<tauri::Wry>) -> AppResult<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    let mut vault_manager = vault_manager_state.0.lock().unwrap();
+
+    vault_manager.close_active_vault()
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Sets how many backups are kept of every vault, takes effect the next time a vault is written.
+pub fn set_backup_count(
+    backup_count: usize,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<()> {
+    let vault_manager_state: tauri::State<VaultManagerState> = app_handle
+        .try_state()
+        .expect("`VaultManager` should already be managed");
+    vault_manager_state
+        .0
+        .lock()
+        .unwrap()
+        .set_backup_count(backup_count);
+
+    let config_state: tauri::State<ConfigState> = app_handle.state();
+    config_state
+        .state
+        .lock()
+        .unwrap()
+        .set_backup_count(backup_count);
+    Ok(config_state.write()?)
+}
+
+#[tauri::command]
+/// **SHOULD ONLY BE CALLED FROM WEBVIEW** <br>
+/// Sets the strength score from 0 to 4 new master passwords need, when a vault is created or its
+/// master password changed or recovered.
+pub fn set_min_master_password_score(
+    score: u8,
+    app_handle: tauri::AppHandle<tauri::Wry>,
+) -> AppResult<()> {
+    if score > 4 {
+        return Err(AppError::InvalidPolicy(
+            "The minimum score has to be between 0 and 4".into(),
+        ));
+    }
+    let config_state: tauri::State<ConfigState> = app_handle.state();
+    config_state
+        .state
+        .lock()
+        .unwrap()
+        .set_min_master_password_score(score);
+    Ok(config_state.write()?)
+}
+
+/// Derive the encryption key from the master password using the vault's `kdf`
+/// (Argon2id for new vaults, PBKDF2-HMAC-SHA256 for vaults created before Argon2id was supported).
 ///
-/// Automatically generate a 128-bit salt and perform SHA512 + PBKDF2 + HMAC with 100k iterations
+/// Automatically generates a 128-bit salt if `predefined_salt` is `None`.
 ///
 /// Returns a tuple of `(256-bit encryption key, salt)`
 fn derive_encryption_key(
     master_password: &str,
+    kdf: Kdf,
     predefined_salt: Option<[u8; SALT_SIZE]>,
-) -> ([u8; KEY_SIZE], [u8; SALT_SIZE]) {
-    const ITERATIONS: u32 = 100_000;
+) -> EncryptionResult<(SecretKey, [u8; SALT_SIZE])> {
     let salt = match predefined_salt {
         Some(s) => s,
         None => rand::thread_rng().gen::<[u8; SALT_SIZE]>(),
     };
-    (
-        pbkdf2_hmac_array::<Sha256, KEY_SIZE>(master_password.as_bytes(), &salt, ITERATIONS),
-        salt,
+    Ok((kdf.derive_key(master_password.as_bytes(), &salt)?, salt))
+}
+
+/// Check a new master password for the vault `vault_name` scores at least `minimum`, see
+/// [strength::estimate].
+fn check_master_password(master_password: &str, vault_name: &str, minimum: u8) -> AppResult<()> {
+    let score = strength::estimate(master_password, &[vault_name]).score;
+    if score < minimum {
+        return Err(AppError::WeakPassword { score, minimum });
+    }
+    Ok(())
+}
+
+/// Decrypt a wrapped internal key with a key derived from the master password (or the recovery key).
+///
+/// A failed decryption here means the password or recovery key was wrong, so it's reported as
+/// [AppError::WrongPassword] rather than a generic encryption error.
+fn unwrap_internal_key(
+    wrapped_key: &[u8],
+    key: &SecretKey,
+    nonce_bytes: [u8; NONCE_SIZE],
+) -> AppResult<SecretKey> {
+    let internal_key = match decrypt_ciphertext(wrapped_key, key, nonce_bytes, &[]) {
+        Err(EncryptionError::Aes(_)) => return Err(AppError::WrongPassword),
+        result => result?,
+    };
+    key_from_slice(internal_key.unsecure())
+        .ok_or(EncryptionError::InvalidLength(InvalidLength).into())
+}
+
+/// Decrypt the entries and folders of a parsed vault file with the internal key.
+///
+/// The decrypted bytes are wiped as soon as the contents are deserialized.
+fn decrypt_contents(file: &VaultFile, internal_key: &SecretKey) -> AppResult<VaultContents> {
+    // The password was right if we got this far, so failing to authenticate the entries means
+    // the file was changed.
+    let decrypted_bytes = decrypt_ciphertext(
+        &file.ciphertext,
+        internal_key,
+        file.nonce,
+        file.associated_data(),
     )
+    .map_err(|e| match e {
+        EncryptionError::Aes(_) => AppError::Integrity,
+        e => e.into(),
+    })?;
+    Ok(format::deserialize_contents(
+        file.version,
+        decrypted_bytes.unsecure(),
+    )?)
 }
 
 /// Takes the bytes to encrypt and the encryption key and creates the ciphertext
 /// A 96-bit nonce is randomly generated and used during the AES256-GCM process.
 ///
-/// The generated ciphertext will have a 16 byte authentication tag appended to it.
+/// The generated ciphertext will have a 16 byte authentication tag appended to it, which also
+/// covers the (unencrypted) `associated_data`.
 ///
 /// Returns a tuple of (nonce, ciphertext)
 fn encrypt_plaintext(
     bytes: &[u8],
-    key_bytes: [u8; KEY_SIZE],
+    key: &SecretKey,
+    associated_data: &[u8],
 ) -> EncryptionResult<([u8; NONCE_SIZE], Vec<u8>)> {
-    let cipher = Aes256Gcm::new_from_slice(&key_bytes)?;
+    let cipher = Aes256Gcm::new_from_slice(key.unsecure())?;
 
     let nonce_bytes = rand::thread_rng().gen::<[u8; NONCE_SIZE]>();
     let nonce = Nonce::from_slice(&nonce_bytes); // 96-bits; unique per message
 
-    let ciphertext = cipher.encrypt(nonce, bytes)?;
+    let payload = Payload {
+        msg: bytes,
+        aad: associated_data,
+    };
+    let ciphertext = cipher.encrypt(nonce, payload)?;
     Ok((nonce_bytes, ciphertext))
 }
 
-/// Accepts the ciphertext, encryption key, and nonce and decrypts the ciphertext
+/// Accepts the ciphertext, encryption key, nonce and the associated data it was encrypted with
+/// and decrypts the ciphertext
 ///
-/// Returns the decrypted ciphertext or an error.
-/// Error would indicate that either the key is wrong or the ciphertext was changed.
+/// Returns the decrypted ciphertext, wiped when it's dropped, or an error.
+/// Error would indicate that either the key is wrong or the ciphertext or associated data was changed.
 fn decrypt_ciphertext(
     ciphertext: &[u8],
-    key_bytes: [u8; KEY_SIZE],
+    key: &SecretKey,
     nonce_bytes: [u8; NONCE_SIZE],
-) -> EncryptionResult<Vec<u8>> {
-    let cipher = Aes256Gcm::new_from_slice(&key_bytes)?;
+    associated_data: &[u8],
+) -> EncryptionResult<SecStr> {
+    let cipher = Aes256Gcm::new_from_slice(key.unsecure())?;
     let nonce: &GenericArray<u8, typenum::U12> = Nonce::from_slice(&nonce_bytes);
 
-    hex_print("ciphertext", &ciphertext);
-
-    Ok(cipher.decrypt(nonce, ciphertext)?)
-}
-
-/// Accepts the ciphertext, encryption key, and nonce and decrypts the ciphertext
-///
-/// Returns the decrypted ciphertext or an error.
-/// Error would indicate that either the key is wrong or the ciphertext was changed.
-fn decrypt_ciphertext_of_size<const N: usize>(
-    ciphertext: &[u8],
-    key_bytes: [u8; KEY_SIZE],
-    nonce_bytes: [u8; NONCE_SIZE],
-) -> EncryptionResult<[u8; N]> {
-    let cipher = Aes256Gcm::new_from_slice(&key_bytes)?;
-    let nonce: &GenericArray<u8, typenum::U12> = Nonce::from_slice(&nonce_bytes);
-
-    hex_print("ciphertext", &ciphertext);
-
-    let text = cipher.decrypt(nonce, &*ciphertext.to_vec())?;
-    Ok(text.try_into().unwrap_or_else(|v: Vec<u8>| {
-        panic!("Expected a Vec of length {} but it was {}", N, v.len())
-    }))
-}
-
-fn hex_print(prefix: &str, bytes: &[u8]) {
-    print!("{}: ", prefix);
-    for byte in bytes {
-        print!("{:02X}", byte);
-    }
-    println!();
+    let payload = Payload {
+        msg: ciphertext,
+        aad: associated_data,
+    };
+    Ok(SecStr::new(cipher.decrypt(nonce, payload)?))
 }